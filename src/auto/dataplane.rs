@@ -1,4 +1,7 @@
-use crate::stats::Stats;
+use crate::{
+    port::{get_actual_baud, get_port_config},
+    stats::{DEFAULT_CONFIDENCE, DriverCounters, Stats, ber_upper_bound},
+};
 use std::time::Duration;
 
 /// Result of running one test (local side).
@@ -7,6 +10,11 @@ pub struct TestOutcome {
     /// true = PASS, false = FAIL
     pub pass: bool,
 
+    /// baud rate requested for the test
+    pub baud: u32,
+    /// baud rate the driver reported applying (if available)
+    pub actual_baud: Option<u32>,
+
     /// total frames received
     pub rx_frames: u64,
     /// total bytes received
@@ -16,15 +24,39 @@ pub struct TestOutcome {
     pub bad_crc: u64,
     /// number of sequence number gaps
     pub seq_gaps: u64,
+    /// bytes the receiver dropped resynchronising on frame boundaries
+    pub discarded: u64,
+
+    /// payload bits compared against the pattern / found flipped, lost
+    /// frames counting as all flipped
+    pub bits_compared: u64,
+    pub bit_errors: u64,
+    /// bit_errors / bits_compared (None when nothing could be compared)
+    pub ber: Option<f64>,
+    /// highest BER the bits compared still allow at `confidence`
+    pub ber_bound: Option<f64>,
+    pub confidence: f64,
+    /// BER the bound had to reach (`--target-ber`)
+    pub target_ber: Option<f64>,
 
-    /// hardware FIFO overrun count (if available)
+    /// hardware FIFO + tty buffer overruns on the RX side (if available)
     pub overruns: u64,
-    /// other error flags, packed as bitmask if driver exposes them
+    /// driver error flags seen on either side, packed as `stats::ERR_*` bits
     pub errors: u32,
+    /// driver counter deltas of the receiving / transmitting port
+    pub rx_driver: Option<DriverCounters>,
+    pub tx_driver: Option<DriverCounters>,
 
     /// measured receive rate in bits per second
     pub rate_bps: u64,
 
+    /// shortest time from a ping's last byte to its clean reply's first, in µs
+    /// (turnaround test only)
+    pub turnaround_us: Option<u64>,
+    /// breaks sent / detected (break test only)
+    pub breaks_sent: Option<u64>,
+    pub breaks_seen: Option<u64>,
+
     /// reason for failure (optional, e.g. "crc errors", "timeout")
     pub reason: Option<String>,
 }
@@ -51,24 +83,120 @@ impl TestOutcome {
 
         Self {
             pass,
+            baud: get_port_config().baud,
+            actual_baud: get_actual_baud(),
             rx_frames: rx_stats.ok,
             rx_bytes: rx_stats.bytes,
-            bad_crc: 0,
+            bad_crc: rx_stats.bad,
             seq_gaps: rx_stats.lost,
-            overruns: 0,
-            errors: rx_stats.bad as u32,
+            discarded: rx_stats.discarded,
+            bits_compared: rx_stats.bits_compared,
+            bit_errors: rx_stats.bit_errors,
+            ber: rx_stats.ber(),
+            ber_bound: ber_upper_bound(
+                rx_stats.bits_compared,
+                rx_stats.bit_errors,
+                DEFAULT_CONFIDENCE,
+            ),
+            confidence: DEFAULT_CONFIDENCE,
+            target_ber: None,
+            overruns: rx_stats.driver.map_or(0, |d| d.overruns()),
+            errors: [rx_stats.driver, tx_stats.driver]
+                .iter()
+                .flatten()
+                .fold(0, |mask, d| mask | d.error_mask()),
+            rx_driver: rx_stats.driver,
+            tx_driver: tx_stats.driver,
             rate_bps: bps_bits,
+            turnaround_us: None,
+            breaks_sent: None,
+            breaks_seen: None,
             reason,
         }
     }
 
+    /// Quote the BER bound at `confidence` and, given a `target`, fail a
+    /// passing test whose bound falls short of it: errors were seen, or too
+    /// few bits went through to tell.
+    pub fn check_ber(&mut self, confidence: f64, target: Option<f64>) {
+        self.confidence = confidence;
+        self.ber_bound = ber_upper_bound(self.bits_compared, self.bit_errors, confidence);
+        self.target_ber = target;
+        if let Some(target) = target
+            && self.pass
+            && !self.ber_bound.is_some_and(|b| b <= target)
+        {
+            self.pass = false;
+            self.reason = Some("ber target not reached".into());
+        }
+    }
+
+    /// Outcome of a turnaround test, where the pinging side (`ping_stats`)
+    /// counted the replies and the answering side only echoed.
+    pub fn from_turnaround_stats(ping_stats: Stats, reply_stats: Stats) -> Self {
+        let turnaround_us = ping_stats.turnaround_us;
+        let mut outcome = Self::from_test_stats(reply_stats, ping_stats);
+        outcome.pass = turnaround_us.is_some();
+        outcome.reason = match turnaround_us {
+            Some(_) => None,
+            None if outcome.rx_frames == 0 => Some("no replies received".into()),
+            None => Some("no clean turnaround gap".into()),
+        };
+        outcome.turnaround_us = turnaround_us;
+        outcome
+    }
+
+    /// Outcome of a break test: every break must be seen by the receiver,
+    /// either as a PARMRK mark or in the driver's `brk` counter, and every
+    /// frame around them must still parse.
+    pub fn from_break_stats(tx_stats: Stats, rx_stats: Stats) -> Self {
+        let sent = tx_stats.breaks.unwrap_or(0);
+        let seen = match (rx_stats.breaks, rx_stats.driver) {
+            (None, None) => None,
+            (marked, driver) => Some(marked.unwrap_or(0).max(driver.map_or(0, |d| d.brk as u64))),
+        };
+        let frames_sent = tx_stats.ok;
+        let mut outcome = Self::from_test_stats(tx_stats, rx_stats);
+        let recovered = outcome.pass && outcome.rx_frames == frames_sent;
+        outcome.reason = if sent == 0 {
+            Some("break not sent".into())
+        } else if seen.is_none() {
+            Some("break detection unavailable".into())
+        } else if seen.unwrap_or(0) < sent {
+            Some("break not detected".into())
+        } else if !recovered {
+            Some("link did not recover".into())
+        } else {
+            None
+        };
+        outcome.pass = outcome.reason.is_none();
+        outcome.breaks_sent = Some(sent);
+        outcome.breaks_seen = seen;
+        outcome
+    }
+
+    /// Outcome of a modem-lines test: `driver_stats` counts the steps whose
+    /// levels the peer saw correctly (ok), wrongly (bad) or not at all (lost).
+    pub fn from_modem_lines_stats(driver_stats: Stats, sampler_stats: Stats) -> Self {
+        let mut outcome = Self::from_test_stats(sampler_stats, driver_stats);
+        outcome.reason = match outcome.pass {
+            true => None,
+            false if outcome.bad_crc > 0 => Some("line mismatch".into()),
+            false => Some("no line state reply".into()),
+        };
+        outcome
+    }
+
     pub fn log(&self) {
         eprintln!(
-            "[auto] {} frames={} bytes={} bad_crc={} gaps={} overruns={} errors=0x{:X} rate_bps={} reason={}",
+            "[auto] {} baud={} actual={} frames={} bytes={} bad_crc={} gaps={} overruns={} errors=0x{:X} rate_bps={} reason={}",
             match self.pass {
                 true => "PASS",
                 false => "FAIL",
             },
+            self.baud,
+            self.actual_baud
+                .map_or_else(|| "unknown".to_string(), |b| b.to_string()),
             self.rx_frames,
             self.rx_bytes,
             self.bad_crc,
@@ -78,5 +206,46 @@ impl TestOutcome {
             self.rate_bps,
             self.reason.as_deref().unwrap_or("none"),
         );
+        if self.discarded > 0 {
+            eprintln!(
+                "[auto]   discarded {} bytes resyncing on frame boundaries",
+                self.discarded
+            );
+        }
+        if let Some(ber) = self.ber {
+            eprintln!(
+                "[auto]   ber={:.2e} ({} of {} payload bits flipped)",
+                ber, self.bit_errors, self.bits_compared
+            );
+        }
+        if let Some(bound) = self.ber_bound {
+            eprintln!(
+                "[auto]   ber<={:.2e} at {}% confidence{}",
+                bound,
+                (self.confidence * 1000.0).round() / 10.0,
+                self.target_ber
+                    .map_or_else(String::new, |t| format!(" (target {:.0e})", t))
+            );
+        }
+        if let Some(t) = self.turnaround_us {
+            eprintln!(
+                "[auto]   turnaround: peer answers cleanly {}us after our last stop bit",
+                t
+            );
+        }
+        if let Some(sent) = self.breaks_sent {
+            eprintln!(
+                "[auto]   breaks: sent={} seen={}",
+                sent,
+                self.breaks_seen
+                    .map_or_else(|| "unknown".to_string(), |b| b.to_string())
+            );
+        }
+        if let Some(d) = self.rx_driver {
+            eprintln!("[auto]   driver rx: {}", d);
+        }
+        if let Some(d) = self.tx_driver {
+            eprintln!("[auto]   driver tx: {}", d);
+        }
     }
 }
@@ -3,14 +3,18 @@ use std::sync::atomic::Ordering;
 use std::time::Duration;
 use uuid::Uuid;
 
-use crate::cli::AutoOpts;
+use crate::cli::{AutoOpts, PortConfig};
 use crate::port::{
-    PORT_DEBUG, open_control, port_default_config, retune_for_config, wait_for_command, write_line,
+    Channels, PORT_DEBUG, enable_rs485, format_mismatch, open_channels, probe_config,
+    reconnect_path, reopen_with_backoff, retune_for_config, set_control_config, wait_for_command,
+    write_line,
 };
 use crate::proto::command::CtrlCommand;
 use crate::proto::parser::{format_command, parse_command};
+use crate::stats::DEFAULT_CONFIDENCE;
 use crate::test::runner::run_hammer_test;
 use crate::test::test_config::TestConfig;
+use crate::transport::is_disconnect;
 
 pub mod dataplane;
 
@@ -18,16 +22,37 @@ pub fn run(args: AutoOpts) -> Result<()> {
     if args.debug {
         PORT_DEBUG.store(true, Ordering::Relaxed);
     }
-    // Open control channel at 115200 8N1 (line-mode)
-    let mut port = open_control(&args.dev)
-        .with_context(|| format!("opening control channel on {}", args.dev))?;
+    set_control_config(args.control.port_config());
+    let reopen_dev = reconnect_path(&args.dev, &args.reconnect);
+    let open = |dev: &str| {
+        open_channels(dev, args.control_dev.as_deref(), &args.open_opts(), true)
+            .with_context(|| format!("opening control channel on {}", dev))
+    };
+    // Open control channel at 115200 8N1 (or --control-*), line-mode
+    let mut ch = open(&args.dev)?;
+    loop {
+        enable_rs485(&mut *ch.data, &args.rs485)?;
+        match serve(&mut ch, &args) {
+            Err(e) if args.reconnect.reconnect && is_disconnect(&e) => {
+                eprintln!("[auto] lost the link: {e:#}");
+                // Release the dead handles (and our lock file) first
+                drop(ch);
+                ch = reopen_with_backoff(&reopen_dev, &args.reconnect, || open(&reopen_dev));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Run the auto (slave) loop on already opened channels.
+pub fn serve(ch: &mut Channels, args: &AutoOpts) -> Result<()> {
     // IDs
     let my_auto_id = Uuid::new_v4().to_string();
-    let mut master_id = wait_for_master_sync(&mut *port, &my_auto_id)?;
+    let mut master_id = wait_for_master_sync(ch, &my_auto_id)?;
 
     loop {
         let cmd = match wait_for_command(
-            &mut *port,
+            &mut ch.ctrl,
             Some(Duration::from_millis(args.inactive_timeout_ms)),
             |line: &str| {
                 let result = parse_command(line);
@@ -39,10 +64,11 @@ pub fn run(args: AutoOpts) -> Result<()> {
             },
         ) {
             Ok(c) => c,
+            Err(e) if is_disconnect(&e) => return Err(e),
             Err(e) => {
                 eprintln!("[auto] error waiting for command: {}", e);
                 eprintln!("[auto] assuming master inactive, returning to HELLO");
-                master_id = wait_for_master_sync(&mut *port, &my_auto_id)?;
+                master_id = wait_for_master_sync(ch, &my_auto_id)?;
                 continue;
             }
         };
@@ -53,22 +79,46 @@ pub fn run(args: AutoOpts) -> Result<()> {
                 baud,
                 parity,
                 bits,
+                stop_bits,
                 flow,
+                strict,
             } => {
-                // ACK with same fields
+                let port_config = PortConfig {
+                    baud,
+                    parity,
+                    bits,
+                    flow,
+                    stop_bits,
+                };
+                // Try it first: the ACK still goes out on the current config
+                let idle = ch.idle_config();
+                let mismatch = probe_config(&mut *ch.data, &port_config, &idle)
+                    .with_context(|| "probing config for CONFIG SET")?;
+                // ACK with same fields, plus what the driver would not do
                 let ack = CtrlCommand::ConfigSetAck {
                     id: my_auto_id.clone(),
                     baud,
                     parity,
                     bits,
+                    stop_bits,
                     flow,
+                    mismatch: mismatch.clone(),
                 };
-                write_line(&mut *port, &format_command(&ack))?;
-                retune_for_config(&mut *port, baud, parity, bits, flow)
+                write_line(&mut ch.ctrl, &format_command(&ack))?;
+                if strict && !mismatch.is_empty() {
+                    eprintln!(
+                        "[auto] not applying config from {}: driver{}",
+                        id,
+                        format_mismatch(&mismatch)
+                    );
+                    continue;
+                }
+                retune_for_config(&mut *ch.data, &port_config)
                     .with_context(|| "retuning for CONFIG SET")?;
+                ch.leave_data()?;
                 eprintln!(
-                    "[auto] config set by {}: baud={} parity={:?} bits={} flow={:?}",
-                    id, baud, parity, bits, flow
+                    "[auto] config set by {}: baud={} parity={:?} bits={} stop={} flow={:?}",
+                    id, baud, parity, bits, stop_bits, flow
                 );
             }
             CtrlCommand::TestBegin {
@@ -78,6 +128,9 @@ pub fn run(args: AutoOpts) -> Result<()> {
                 duration_ms,
                 payload,
                 dir,
+                break_ms,
+                format,
+                pattern,
             } => {
                 eprintln!("[auto] TEST BEGIN from master id={}", id);
                 if id != master_id {
@@ -89,7 +142,7 @@ pub fn run(args: AutoOpts) -> Result<()> {
                 }
 
                 match run_hammer_test(
-                    &mut *port,
+                    ch,
                     &my_auto_id,
                     TestConfig {
                         name,
@@ -97,10 +150,18 @@ pub fn run(args: AutoOpts) -> Result<()> {
                         frames,
                         payload,
                         dir,
+                        break_ms,
+                        format,
+                        pattern,
+                        // Only the master judges the outcome
+                        confidence: DEFAULT_CONFIDENCE,
+                        target_ber: None,
                     },
                     false,
+                    None,
                 ) {
                     Ok(_) => {}
+                    Err(e) if is_disconnect(&e) => return Err(e),
                     Err(e) => {
                         eprintln!("[auto] error during test: {}", e);
                     }
@@ -113,6 +174,17 @@ pub fn run(args: AutoOpts) -> Result<()> {
                 // You can parse and mirror to console if you want.
             }
 
+            // A master that restarted (or reconnected to a control socket)
+            CtrlCommand::Hello { id } => {
+                eprintln!(
+                    "[auto] HELLO from master id={} mid-session, starting over",
+                    id
+                );
+                ch.reset_control()?;
+                ack_master(ch, &my_auto_id)?;
+                master_id = id;
+            }
+
             // Termination -------------------------------------------------
             CtrlCommand::Terminate { .. } => {
                 eprintln!("[auto] received TERMINATE from master id={}", master_id);
@@ -120,8 +192,11 @@ pub fn run(args: AutoOpts) -> Result<()> {
                 let ack = CtrlCommand::TerminateAck {
                     id: my_auto_id.clone(),
                 };
-                write_line(&mut *port, &format_command(&ack))?;
-                master_id = wait_for_master_sync(&mut *port, &my_auto_id)?;
+                write_line(&mut ch.ctrl, &format_command(&ack))?;
+                if args.once {
+                    return Ok(());
+                }
+                master_id = wait_for_master_sync(ch, &my_auto_id)?;
             }
             _ => {
                 eprintln!("[auto] warning: ignoring unexpected command {:?}", cmd);
@@ -131,12 +206,12 @@ pub fn run(args: AutoOpts) -> Result<()> {
 }
 
 /* -------------------- helpers -------------------- */
-fn wait_for_master_sync(port: &mut dyn serialport::SerialPort, my_id: &str) -> Result<String> {
+fn wait_for_master_sync(ch: &mut Channels, my_id: &str) -> Result<String> {
     // Ensure port is in default config
-    port_default_config(port)?;
+    ch.reset_control()?;
     eprintln!("[auto] id={} awaiting master", my_id);
 
-    let master_id = wait_for_command(port, None, |line: &str| {
+    let master_id = wait_for_command(&mut ch.ctrl, None, |line: &str| {
         if let Ok(cmd) = parse_command(line)
             && let CtrlCommand::Hello { id } = cmd
         {
@@ -150,9 +225,13 @@ fn wait_for_master_sync(port: &mut dyn serialport::SerialPort, my_id: &str) -> R
         None
     })?;
 
+    ack_master(ch, my_id)?;
+    Ok(master_id)
+}
+
+fn ack_master(ch: &mut Channels, my_id: &str) -> Result<()> {
     let ack = CtrlCommand::Ack {
         id: my_id.to_string(),
     };
-    write_line(port, &format_command(&ack))?;
-    Ok(master_id)
+    write_line(&mut ch.ctrl, &format_command(&ack))
 }
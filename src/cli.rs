@@ -1,10 +1,13 @@
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
 use clap::{Args, Parser, Subcommand};
-use std::time::Duration;
+use std::{path::PathBuf, time::Duration};
 
 use crate::{
+    pattern::Pattern,
     port::DEFAULT_CONFIG,
-    proto::command::{Direction, FlowControl, Parity, TestName},
+    proto::command::{Direction, FlowControl, FrameEncoding, Parity, TestName},
+    stats::DEFAULT_CONFIDENCE,
+    transport::OpenOpts,
 };
 
 #[derive(Parser, Debug, Clone)]
@@ -23,7 +26,11 @@ pub enum Cmd {
     /// Automated UART validation master/slave
     Auto(AutoOpts),
     /// Run specific tests (internal)
-    Test(TestOpts),
+    Test(Box<TestOpts>),
+    /// Run master and auto against each other over a PTY pair
+    Selftest(SelftestOpts),
+    /// List serial ports with driver, USB details and who has them open
+    List(ListOpts),
 }
 
 #[derive(Args, Debug, Clone)]
@@ -37,6 +44,82 @@ pub struct SerialOpts {
     /// Enable RTS/CTS
     #[arg(long, default_value_t = false)]
     pub rtscts: bool,
+    #[command(flatten)]
+    pub lock: LockOpts,
+}
+
+/// Line settings of the control channel (HELLO, CONFIG SET, TEST ...)
+#[derive(Args, Debug, Clone)]
+pub struct ControlOpts {
+    /// Control channel baud rate (both sides must agree, or use `--control-probe`)
+    #[arg(long, default_value_t = 115_200)]
+    pub control_baud: u32,
+    /// Control channel parity
+    #[arg(long, default_value = "none", value_parser = ["none", "even", "odd"])]
+    pub control_parity: String,
+    /// Control channel data bits (7 or 8; the protocol is ASCII)
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u8).range(7..=8))]
+    pub control_bits: u8,
+}
+
+impl ControlOpts {
+    pub fn port_config(&self) -> PortConfig {
+        PortConfig {
+            baud: self.control_baud,
+            parity: match self.control_parity.as_str() {
+                "even" => Parity::Even,
+                "odd" => Parity::Odd,
+                _ => Parity::None,
+            },
+            bits: self.control_bits,
+            ..DEFAULT_CONFIG
+        }
+    }
+}
+
+/// Exclusive access to a local device: TIOCEXCL plus a UUCP `LCK..` file
+#[derive(Args, Debug, Clone)]
+pub struct LockOpts {
+    /// Directory for the `LCK..<tty>` lock file
+    #[arg(long, default_value = "/var/lock")]
+    pub lock_dir: PathBuf,
+    /// Don't create a lock file (TIOCEXCL still applies)
+    #[arg(long, default_value_t = false)]
+    pub no_lock: bool,
+}
+
+impl LockOpts {
+    pub fn lock_dir(&self) -> Option<PathBuf> {
+        (!self.no_lock).then(|| self.lock_dir.clone())
+    }
+}
+
+/// What to do when the device goes away mid-run (USB adapter unplugged or re-enumerated)
+#[derive(Args, Debug, Clone)]
+pub struct ReconnectOpts {
+    /// Reopen the device with backoff instead of exiting
+    #[arg(long, default_value_t = false)]
+    pub reconnect: bool,
+    /// Reopen through the device's /dev/serial/by-id link, which follows the adapter to a new ttyUSB number
+    #[arg(long, default_value_t = false)]
+    pub reconnect_by_id: bool,
+    /// Longest wait between reopen attempts
+    #[arg(long, default_value_t = 5_000)]
+    pub reconnect_max_ms: u64,
+}
+
+/// RS-485 half-duplex mode, applied with `TIOCSRS485` (Linux)
+#[derive(Args, Debug, Clone, Default)]
+pub struct Rs485Opts {
+    /// Put the port in RS-485 mode: RTS drives the transceiver while sending
+    #[arg(long, default_value_t = false)]
+    pub rs485: bool,
+    /// Assert RTS this long before the first start bit
+    #[arg(long, default_value_t = 0)]
+    pub rs485_delay_before_ms: u32,
+    /// Keep RTS asserted this long after the last stop bit
+    #[arg(long, default_value_t = 0)]
+    pub rs485_delay_after_ms: u32,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -79,6 +162,9 @@ pub struct TxOpts {
 pub struct TestOpts {
     #[arg(long)]
     pub dev: String,
+    /// Separate link for the control protocol (UART, tcp://host:port or unix:///path); `--dev` then carries only data
+    #[arg(long)]
+    pub control_dev: Option<String>,
     // test selection / params
     #[arg(long, default_value = "max-rate,fifo-residue")]
     pub tests: String,
@@ -88,9 +174,11 @@ pub struct TestOpts {
     pub parity: String,
     #[arg(long, default_value = "8")]
     pub bits: String,
+    #[arg(long, default_value = "1")] // list of 1,2
+    pub stop_bits: String,
     #[arg(long, default_value = "tx,rx")] // list of tx,rx,both
     pub dir: String,
-    #[arg(long, default_value = "none")] // none,rtscts
+    #[arg(long, default_value = "none")] // none,rtscts,xonxoff
     pub flow: String,
     #[arg(long, default_value_t = 32)]
     pub payload: usize,
@@ -98,15 +186,48 @@ pub struct TestOpts {
     pub frames: usize,
     #[arg(long)]
     pub duration_ms: Option<u64>,
+    /// How long the break test holds the line in break
+    #[arg(long, default_value_t = 100)]
+    pub break_ms: u64,
+    /// Data-plane frame encoding: text (hex, nibbles below 7 bits), bin (length-prefixed, CRC-32C) or cobs (zero-delimited, CRC-32C)
+    #[arg(long, default_value = "text")]
+    pub format: String,
+    /// Payload pattern: counter, prbs7, prbs15, prbs23, prbs31, 0x55, 0xaa, zeros, ones, walking-ones or random[:<seed>]
+    #[arg(long, default_value = "counter")]
+    pub pattern: String,
+    /// Size each max-rate run (frames and duration, per baud) to show this bit error rate, e.g. 1e-9; fails configs whose bound misses it
+    #[arg(long)]
+    pub target_ber: Option<f64>,
+    /// Confidence level of the BER upper bound
+    #[arg(long, default_value_t = DEFAULT_CONFIDENCE)]
+    pub confidence: f64,
+    /// What to do with a config either driver does not apply as asked
+    #[arg(long, default_value = "skip")] // skip,fail,run
+    pub on_mismatch: String,
     // protocol timings
     #[arg(long, default_value_t = 500)]
     pub hello_ms: u64,
     #[arg(long, default_value_t = 4_000)]
     pub hello_backoff_max_ms: u64,
+    /// How long to wait for the auto side to answer CONFIG SET
     #[arg(long, default_value_t = 10_000)]
     pub repeat_timeout_ms: u64,
     #[arg(long, default_value_t = 2)]
     pub repeat_hz: u32, // “current baud / 2” in spec; we’ll map to 2 Hz control repeats
+    /// Shell command run on every retune of a tcp:// device (settings in HAMMER_* env)
+    #[arg(long)]
+    pub retune_hook: Option<String>,
+    #[command(flatten)]
+    pub rs485: Rs485Opts,
+    #[command(flatten)]
+    pub lock: LockOpts,
+    #[command(flatten)]
+    pub reconnect: ReconnectOpts,
+    #[command(flatten)]
+    pub control: ControlOpts,
+    /// Control bauds to cycle through until the auto side answers HELLO (e.g. "115200,57600,9600")
+    #[arg(long)]
+    pub control_probe: Option<String>,
     /// Print each CMD line
     #[arg(long, default_value_t = false)]
     pub debug: bool,
@@ -116,6 +237,9 @@ pub struct TestOpts {
 pub struct AutoOpts {
     #[arg(long)]
     pub dev: String,
+    /// Separate link for the control protocol (UART, or a tcp://addr:port / unix:///path socket to listen on)
+    #[arg(long)]
+    pub control_dev: Option<String>,
     #[arg(long, default_value_t = 10_000)]
     pub repeat_timeout_ms: u64,
     /// Print each CMD line
@@ -124,6 +248,77 @@ pub struct AutoOpts {
     /// Inactive time out
     #[arg(long, default_value_t = 60_000)]
     pub inactive_timeout_ms: u64,
+    /// Shell command run on every retune of a tcp:// device (settings in HAMMER_* env)
+    #[arg(long)]
+    pub retune_hook: Option<String>,
+    #[command(flatten)]
+    pub rs485: Rs485Opts,
+    #[command(flatten)]
+    pub lock: LockOpts,
+    #[command(flatten)]
+    pub reconnect: ReconnectOpts,
+    #[command(flatten)]
+    pub control: ControlOpts,
+    /// Exit after the first TERMINATE instead of waiting for the next master
+    #[arg(long, default_value_t = false)]
+    pub once: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SelftestOpts {
+    #[arg(long, default_value = "max-rate")]
+    pub tests: String,
+    #[arg(long, default_value = "115200")]
+    pub bauds: String,
+    #[arg(long, default_value = "8")]
+    pub bits: String,
+    #[arg(long, default_value = "tx,rx")] // list of tx,rx,both
+    pub dir: String,
+    #[arg(long, default_value_t = 32)]
+    pub payload: usize,
+    #[arg(long, default_value_t = 50)]
+    pub frames: usize,
+    /// Data-plane frame encoding (text,bin,cobs)
+    #[arg(long, default_value = "text")]
+    pub format: String,
+    /// Payload pattern (counter,prbs7,prbs15,prbs23,prbs31,0x55,0xaa,zeros,ones,walking-ones,random[:<seed>])
+    #[arg(long, default_value = "counter")]
+    pub pattern: String,
+    /// A PTY always runs 8N1 whatever it is asked, so run mismatched configs anyway
+    #[arg(long, default_value = "run")] // skip,fail,run
+    pub on_mismatch: String,
+    /// Give up if master and auto have not both finished by then
+    #[arg(long, default_value_t = 60_000)]
+    pub timeout_ms: u64,
+    /// Print each CMD line
+    #[arg(long, default_value_t = false)]
+    pub debug: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ListOpts {
+    /// Print a JSON array instead of text
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+    /// Where sysfs is mounted
+    #[arg(long, default_value = "/sys")]
+    pub sysfs_root: String,
+    /// Where device nodes (and serial/by-id) live
+    #[arg(long, default_value = "/dev")]
+    pub dev_root: String,
+    /// Where procfs is mounted, for finding processes holding a port
+    #[arg(long, default_value = "/proc")]
+    pub proc_root: String,
+}
+
+impl AutoOpts {
+    pub fn open_opts(&self) -> OpenOpts {
+        OpenOpts {
+            retune_hook: self.retune_hook.clone(),
+            lock_dir: self.lock.lock_dir(),
+            ..OpenOpts::default()
+        }
+    }
 }
 
 /// Typed pacing model to replace ad-hoc gap handling.
@@ -162,6 +357,17 @@ impl Pacing {
     }
 }
 
+/// What the master does with a config a driver silently changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MismatchPolicy {
+    /// Leave it out of the run
+    Skip,
+    /// Count its tests as failed without running them
+    Fail,
+    /// Run the tests anyway on whatever the drivers applied
+    Run,
+}
+
 /// Cleaned-up struct for a parsed configuration
 #[derive(Debug, Clone, Copy)]
 pub struct PortConfig {
@@ -169,7 +375,7 @@ pub struct PortConfig {
     pub parity: Parity,
     pub bits: u8,
     pub flow: FlowControl,
-    pub stop_bits: u8, // currently always 1
+    pub stop_bits: u8,
 }
 
 impl PortConfig {
@@ -181,31 +387,42 @@ impl PortConfig {
             _ => 1,
         };
 
-        let stop_bits = self.stop_bits as u32; // adjust if you later add a field for stop bits
+        let stop_bits = self.stop_bits as u32;
 
         start_bits + self.bits as u32 + parity_bits + stop_bits
     }
 }
 
 impl TestOpts {
+    pub fn open_opts(&self) -> OpenOpts {
+        OpenOpts {
+            retune_hook: self.retune_hook.clone(),
+            lock_dir: self.lock.lock_dir(),
+            ..OpenOpts::default()
+        }
+    }
+
     pub fn get_port_configs(&self) -> Vec<PortConfig> {
         let bauds = self.get_baud_rates();
         let parities = self.get_parities();
         let bits_list = self.get_bits();
+        let stop_bits_list = self.get_stop_bits();
         let flow_controls = self.get_flow_controls();
 
         let mut configs = Vec::new();
         for &baud in &bauds {
             for &parity in &parities {
                 for &bits in &bits_list {
-                    for &flow in &flow_controls {
-                        configs.push(PortConfig {
-                            baud,
-                            parity,
-                            bits,
-                            flow,
-                            stop_bits: 1,
-                        });
+                    for &stop_bits in &stop_bits_list {
+                        for &flow in &flow_controls {
+                            configs.push(PortConfig {
+                                baud,
+                                parity,
+                                bits,
+                                flow,
+                                stop_bits,
+                            });
+                        }
                     }
                 }
             }
@@ -223,10 +440,25 @@ impl TestOpts {
             .bits
             .split(',')
             .filter_map(|s| s.trim().parse().ok())
+            .filter(|n| (5..=8).contains(n))
             .collect();
         if bits.is_empty() { vec![8] } else { bits }
     }
 
+    pub fn get_stop_bits(&self) -> Vec<u8> {
+        let stop_bits: Vec<u8> = self
+            .stop_bits
+            .split(',')
+            .filter_map(|s| s.trim().parse().ok())
+            .filter(|n| matches!(n, 1 | 2))
+            .collect();
+        if stop_bits.is_empty() {
+            vec![1]
+        } else {
+            stop_bits
+        }
+    }
+
     pub fn get_flow_controls(&self) -> Vec<FlowControl> {
         let flow_controls: Vec<FlowControl> = self
             .flow
@@ -234,6 +466,7 @@ impl TestOpts {
             .filter_map(|s| match s.trim() {
                 "none" => Some(FlowControl::None),
                 "rtscts" => Some(FlowControl::RtsCts),
+                "xonxoff" => Some(FlowControl::XonXoff),
                 _ => None,
             })
             .collect();
@@ -297,15 +530,87 @@ impl TestOpts {
         }
     }
 
+    /// Control bauds to try in turn while waiting for the auto side: the
+    /// configured one first, then `--control-probe` in order
+    pub fn get_control_bauds(&self) -> Vec<u32> {
+        let mut bauds = vec![self.control.control_baud];
+        if let Some(probe) = &self.control_probe {
+            for baud in probe
+                .replace("_", "")
+                .split(',')
+                .filter_map(|s| s.trim().parse().ok())
+            {
+                if !bauds.contains(&baud) {
+                    bauds.push(baud);
+                }
+            }
+        }
+        bauds
+    }
+
+    pub fn get_mismatch_policy(&self) -> MismatchPolicy {
+        match self.on_mismatch.trim() {
+            "fail" => MismatchPolicy::Fail,
+            "run" => MismatchPolicy::Run,
+            _ => MismatchPolicy::Skip,
+        }
+    }
+
+    pub fn get_frame_encoding(&self) -> anyhow::Result<FrameEncoding> {
+        match self.format.trim() {
+            "text" => Ok(FrameEncoding::Text),
+            "bin" => Ok(FrameEncoding::Binary),
+            "cobs" => Ok(FrameEncoding::Cobs),
+            f => bail!("unknown --format '{}' (expected text, bin or cobs)", f),
+        }
+    }
+
+    pub fn get_pattern(&self) -> anyhow::Result<Pattern> {
+        self.pattern
+            .parse()
+            .map_err(|_| anyhow!("unknown --pattern '{}'", self.pattern))
+    }
+
+    pub fn get_target_ber(&self) -> anyhow::Result<Option<f64>> {
+        match self.target_ber {
+            Some(t) if !(t > 0.0 && t < 1.0) => {
+                bail!(
+                    "--target-ber must be between 0 and 1, e.g. 1e-9 (got {})",
+                    t
+                )
+            }
+            t => Ok(t),
+        }
+    }
+
+    pub fn get_confidence(&self) -> anyhow::Result<f64> {
+        match self.confidence {
+            c if c > 0.0 && c < 1.0 => Ok(c),
+            c => bail!(
+                "--confidence must be between 0 and 1, e.g. 0.95 (got {})",
+                c
+            ),
+        }
+    }
+
     pub fn get_test_names(&self) -> Vec<TestName> {
         if self.tests.trim() == "*" {
-            return vec![TestName::MaxRate, TestName::FifoResidue];
+            return vec![
+                TestName::MaxRate,
+                TestName::FifoResidue,
+                TestName::Turnaround,
+                TestName::ModemLines,
+                TestName::Break,
+            ];
         }
         self.tests
             .split(',')
             .filter_map(|s| match s.trim() {
                 "max-rate" => Some(TestName::MaxRate),
                 "fifo-residue" => Some(TestName::FifoResidue),
+                "turnaround" => Some(TestName::Turnaround),
+                "modem-lines" => Some(TestName::ModemLines),
+                "break" => Some(TestName::Break),
                 _ => None,
             })
             .collect()
@@ -1,23 +1,39 @@
 use anyhow::{Context, Result, bail};
+use std::io::{self, Read};
+
+use crate::proto::command::FrameEncoding;
 
 #[derive(Debug, Clone)]
 pub struct Frame {
     pub seq: u64,
+    pub payload: Vec<u8>,
 }
 
-pub fn hexsum(payload_hex: &str) -> Result<u8> {
-    if payload_hex.len() % 2 != 0 {
+fn unhex(payload_hex: &str) -> Result<Vec<u8>> {
+    if !payload_hex.len().is_multiple_of(2) {
         bail!("odd hex length");
     }
-    let mut sum: u8 = 0;
-    for i in (0..payload_hex.len()).step_by(2) {
-        let b = u8::from_str_radix(&payload_hex[i..i + 2], 16).context("bad hex in PAY")?;
-        sum = sum.wrapping_add(b);
-    }
-    Ok(sum)
+    (0..payload_hex.len())
+        .step_by(2)
+        .map(|i| {
+            payload_hex
+                .get(i..i + 2)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .context("bad hex in PAY")
+        })
+        .collect()
+}
+
+fn sum8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
 }
 
 pub fn parse_frame(line: &str) -> Result<Frame> {
+    unpack_frame(line, true)
+}
+
+/// Text frame fields; with `verify` false a wrong SUM is let through.
+fn unpack_frame(line: &str, verify: bool) -> Result<Frame> {
     // tolerate leading/trailing markers and flexible order
     let mut seq = None;
     let mut len = None;
@@ -43,33 +59,691 @@ pub fn parse_frame(line: &str) -> Result<Frame> {
     if pay.len() != len * 2 {
         bail!("len mismatch");
     }
-    let calc = hexsum(&pay)?;
-    if calc != sumrx {
+    let payload = unhex(&pay)?;
+    let calc = sum8(&payload);
+    if verify && calc != sumrx {
         bail!("checksum {}!={}", calc, sumrx);
     }
-    Ok(Frame { seq })
+    Ok(Frame { seq, payload })
 }
 
-pub fn build_frame(seq: u64, len: usize) -> String {
-    // PAY = (i+seq) % 256 pattern
+pub fn build_frame(seq: u64, payload: &[u8]) -> String {
     let mut sum: u8 = 0;
-    let mut s = String::with_capacity(2 * len);
-    for i in 0..len {
-        let b = ((i as u64 + seq) & 0xFF) as u8;
+    let mut s = String::with_capacity(2 * payload.len());
+    for &b in payload {
         sum = sum.wrapping_add(b);
         use std::fmt::Write;
         let _ = write!(s, "{:02X}", b);
     }
-    format!("@@ SEQ={} LEN={} PAY={} SUM={:02X} ##", seq, len, s, sum)
+    format!(
+        "@@ SEQ={} LEN={} PAY={} SUM={:02X} ##",
+        seq,
+        payload.len(),
+        s,
+        sum
+    )
+}
+
+/* ---------- nibble frames for 5/6-bit links ---------- */
+
+/// Marks the start of a nibble frame
+pub const NIBBLE_START: u8 = 0x10;
+/// Ends a nibble frame; like START it is outside the 0x0..=0xF nibble range
+/// and still fits in 5 data bits.
+pub const NIBBLE_END: u8 = 0x1F;
+
+/// Frame for links with fewer than 7 data bits, where hex text cannot pass.
+/// Every byte travels as two 4-bit characters (high nibble first):
+/// START, seq (u32 BE), len (u16 BE), payload, sum (u8), END.
+pub fn build_nibble_frame(seq: u64, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(payload.len() + 7);
+    bytes.extend_from_slice(&(seq as u32).to_be_bytes());
+    bytes.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(payload);
+    bytes.push(sum8(payload));
+
+    let mut out = Vec::with_capacity(2 * bytes.len() + 2);
+    out.push(NIBBLE_START);
+    for b in bytes {
+        out.push(b >> 4);
+        out.push(b & 0x0F);
+    }
+    out.push(NIBBLE_END);
+    out
+}
+
+pub fn parse_nibble_frame(chars: &[u8]) -> Result<Frame> {
+    unpack_nibble_frame(chars, true)
+}
+
+fn unpack_nibble_frame(chars: &[u8], verify: bool) -> Result<Frame> {
+    let chars = chars.strip_suffix(&[NIBBLE_END]).unwrap_or(chars);
+    // tolerate leading garbage before the start marker
+    let start = chars
+        .iter()
+        .rposition(|&c| c == NIBBLE_START)
+        .ok_or_else(|| anyhow::anyhow!("no START"))?;
+    let nibbles = &chars[start + 1..];
+    if !nibbles.len().is_multiple_of(2) {
+        bail!("odd nibble count");
+    }
+    let mut bytes = Vec::with_capacity(nibbles.len() / 2);
+    for pair in nibbles.chunks(2) {
+        if pair[0] > 0x0F || pair[1] > 0x0F {
+            bail!("bad nibble");
+        }
+        bytes.push(pair[0] << 4 | pair[1]);
+    }
+    if bytes.len() < 7 {
+        bail!("short frame");
+    }
+    let seq = u32::from_be_bytes(bytes[0..4].try_into()?) as u64;
+    let len = u16::from_be_bytes(bytes[4..6].try_into()?) as usize;
+    let (pay, sumrx) = (&bytes[6..bytes.len() - 1], bytes[bytes.len() - 1]);
+    if pay.len() != len {
+        bail!("len mismatch");
+    }
+    let calc = sum8(pay);
+    if verify && calc != sumrx {
+        bail!("checksum {}!={}", calc, sumrx);
+    }
+    Ok(Frame {
+        seq,
+        payload: pay.to_vec(),
+    })
+}
+
+/* ---------- binary frames with CRC-32C ---------- */
+
+/// Marks the start of a binary frame
+pub const BIN_SYNC: [u8; 2] = [0xA5, 0x5A];
+/// Sync, len (u16 BE) and seq (u32 BE)
+pub const BIN_HEADER_LEN: usize = 8;
+/// Trailing CRC-32C (u32 BE)
+pub const BIN_TRAILER_LEN: usize = 4;
+/// Longest payload a receiver will wait for; a longer length field is
+/// taken as line noise rather than a frame
+pub const MAX_BIN_PAYLOAD: usize = 4096;
+
+const CRC32C_POLY: u32 = 0x82F6_3B78; // Castagnoli, reflected
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                (c >> 1) ^ CRC32C_POLY
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+pub fn crc32c(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &b| {
+        CRC32C_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Frame for 8-bit links: SYNC, len (u16 BE), seq (u32 BE), payload,
+/// CRC-32C (u32 BE) over everything between SYNC and the CRC.
+pub fn build_binary_frame(seq: u64, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(BIN_HEADER_LEN + payload.len() + BIN_TRAILER_LEN);
+    out.extend_from_slice(&BIN_SYNC);
+    out.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    out.extend_from_slice(&(seq as u32).to_be_bytes());
+    out.extend_from_slice(payload);
+    let crc = crc32c(&out[BIN_SYNC.len()..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+    out
+}
+
+/// Payload length a binary frame header announces, once enough of it is in
+fn binary_len(bytes: &[u8]) -> Option<usize> {
+    let len = bytes.get(2..4)?;
+    Some(u16::from_be_bytes([len[0], len[1]]) as usize)
+}
+
+pub fn parse_binary_frame(bytes: &[u8]) -> Result<Frame> {
+    unpack_binary_frame(bytes, true)
+}
+
+fn unpack_binary_frame(bytes: &[u8], verify: bool) -> Result<Frame> {
+    if !bytes.starts_with(&BIN_SYNC) {
+        bail!("no SYNC");
+    }
+    if bytes.len() < BIN_HEADER_LEN + BIN_TRAILER_LEN {
+        bail!("short frame");
+    }
+    let len = binary_len(bytes).unwrap_or_default();
+    if bytes.len() != BIN_HEADER_LEN + len + BIN_TRAILER_LEN {
+        bail!("len mismatch");
+    }
+    let (body, crc) = bytes.split_at(bytes.len() - BIN_TRAILER_LEN);
+    let crcrx = u32::from_be_bytes(crc.try_into()?);
+    let calc = crc32c(&body[BIN_SYNC.len()..]);
+    if verify && calc != crcrx {
+        bail!("crc {:08X}!={:08X}", calc, crcrx);
+    }
+    let seq = u32::from_be_bytes(body[4..8].try_into()?) as u64;
+    Ok(Frame {
+        seq,
+        payload: body[BIN_HEADER_LEN..].to_vec(),
+    })
+}
+
+/* ---------- COBS frames, zero-delimited ---------- */
+
+/// Ends every COBS frame; it never occurs inside one
+pub const COBS_DELIM: u8 = 0x00;
+
+/// Consistent Overhead Byte Stuffing: rewrites `data` without zero bytes,
+/// at one extra byte per 254.
+pub fn cobs_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 254 + 2);
+    let mut code_at = 0;
+    let mut code = 1u8;
+    out.push(0);
+    for &b in data {
+        if b != 0 {
+            out.push(b);
+            code += 1;
+        }
+        if b == 0 || code == 0xFF {
+            out[code_at] = code;
+            code_at = out.len();
+            code = 1;
+            out.push(0);
+        }
+    }
+    out[code_at] = code;
+    out
+}
+
+pub fn cobs_decode(enc: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(enc.len());
+    let mut i = 0;
+    while i < enc.len() {
+        let code = enc[i] as usize;
+        if code == 0 {
+            bail!("zero in COBS data");
+        }
+        let end = i + code;
+        if end > enc.len() {
+            bail!("COBS block overruns frame");
+        }
+        let block = &enc[i + 1..end];
+        if block.contains(&0) {
+            bail!("zero in COBS data");
+        }
+        out.extend_from_slice(block);
+        i = end;
+        // Every block but a full one stands for a zero, bar the last
+        if code < 0xFF && i < enc.len() {
+            out.push(0);
+        }
+    }
+    Ok(out)
+}
+
+/// Frame for 8-bit links that may carry any byte value: len (u16 BE),
+/// seq (u32 BE), payload, CRC-32C (u32 BE) over the rest, COBS encoded and
+/// closed by `COBS_DELIM`. With the counter pattern the payload sweeps
+/// 0x00 (stuffed), XON/XOFF and 0xFF alike.
+pub fn build_cobs_frame(seq: u64, payload: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(6 + payload.len() + 4);
+    raw.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    raw.extend_from_slice(&(seq as u32).to_be_bytes());
+    raw.extend_from_slice(payload);
+    let crc = crc32c(&raw);
+    raw.extend_from_slice(&crc.to_be_bytes());
+
+    let mut out = cobs_encode(&raw);
+    out.push(COBS_DELIM);
+    out
+}
+
+pub fn parse_cobs_frame(chunk: &[u8]) -> Result<Frame> {
+    unpack_cobs_frame(chunk, true)
+}
+
+fn unpack_cobs_frame(chunk: &[u8], verify: bool) -> Result<Frame> {
+    let chunk = chunk.strip_suffix(&[COBS_DELIM]).unwrap_or(chunk);
+    let raw = cobs_decode(chunk)?;
+    if raw.len() < 10 {
+        bail!("short frame");
+    }
+    let len = u16::from_be_bytes(raw[0..2].try_into()?) as usize;
+    if raw.len() != 6 + len + 4 {
+        bail!("len mismatch");
+    }
+    let (body, crc) = raw.split_at(raw.len() - 4);
+    let crcrx = u32::from_be_bytes(crc.try_into()?);
+    let calc = crc32c(body);
+    if verify && calc != crcrx {
+        bail!("crc {:08X}!={:08X}", calc, crcrx);
+    }
+    let seq = u32::from_be_bytes(body[2..6].try_into()?) as u64;
+    Ok(Frame {
+        seq,
+        payload: body[6..].to_vec(),
+    })
+}
+
+/// On-the-wire encoding the data plane uses for frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameFormat {
+    /// `@@ SEQ=… ##` hex text line, CRLF terminated
+    Hex,
+    /// Packed nibbles for 5/6-bit data sizes
+    Nibble,
+    /// Length-prefixed binary with CRC-32C, 8 data bits only
+    Binary,
+    /// COBS-stuffed binary with CRC-32C, zero-delimited, 8 data bits only
+    Cobs,
+}
+
+impl FrameFormat {
+    /// Hex text needs 7 data bits; narrower links switch to nibbles.
+    pub fn for_bits(bits: u8) -> Self {
+        if bits < 7 {
+            FrameFormat::Nibble
+        } else {
+            FrameFormat::Hex
+        }
+    }
+
+    /// Format for a test's `format=`; text picks hex or nibbles by width.
+    pub fn for_test(encoding: FrameEncoding, bits: u8) -> Self {
+        match encoding {
+            FrameEncoding::Text => Self::for_bits(bits),
+            FrameEncoding::Binary => FrameFormat::Binary,
+            FrameEncoding::Cobs => FrameFormat::Cobs,
+        }
+    }
+
+    /// Last character of every encoded frame; binary frames have none and
+    /// are cut by their length field instead.
+    pub fn delimiter(self) -> Option<u8> {
+        match self {
+            FrameFormat::Hex => Some(b'\n'),
+            FrameFormat::Nibble => Some(NIBBLE_END),
+            FrameFormat::Cobs => Some(COBS_DELIM),
+            FrameFormat::Binary => None,
+        }
+    }
+
+    /// Encode one frame, terminator included.
+    pub fn encode(self, seq: u64, payload: &[u8]) -> Vec<u8> {
+        match self {
+            FrameFormat::Hex => {
+                let mut out = build_frame(seq, payload).into_bytes();
+                out.extend_from_slice(b"\r\n");
+                out
+            }
+            FrameFormat::Nibble => build_nibble_frame(seq, payload),
+            FrameFormat::Binary => build_binary_frame(seq, payload),
+            FrameFormat::Cobs => build_cobs_frame(seq, payload),
+        }
+    }
+
+    /// Decode one frame as cut from the stream by a [`Deframer`].
+    pub fn decode(self, chunk: &[u8]) -> Result<Frame> {
+        match self {
+            FrameFormat::Hex => parse_frame(String::from_utf8_lossy(chunk).trim_end()),
+            FrameFormat::Nibble => parse_nibble_frame(chunk),
+            FrameFormat::Binary => parse_binary_frame(chunk),
+            FrameFormat::Cobs => parse_cobs_frame(chunk),
+        }
+    }
+
+    /// Whether `chunk` holds the start of a frame, as opposed to the tail
+    /// of one whose beginning went by before we started reading. COBS has
+    /// no start marker, so there a chunk counts as a frame when its layout
+    /// holds, CRC aside.
+    pub fn has_frame_start(self, chunk: &[u8]) -> bool {
+        match self {
+            FrameFormat::Hex => chunk.windows(2).any(|w| w == b"@@"),
+            FrameFormat::Nibble => chunk.contains(&NIBBLE_START),
+            FrameFormat::Binary => chunk.starts_with(&BIN_SYNC),
+            FrameFormat::Cobs => self.decode_unchecked(chunk).is_ok(),
+        }
+    }
+
+    /// Like [`decode`](Self::decode), but a frame whose checksum or CRC
+    /// fails still comes back as long as its layout holds, so its payload
+    /// can be compared bit by bit. Its seq may be corrupted too.
+    pub fn decode_unchecked(self, chunk: &[u8]) -> Result<Frame> {
+        match self {
+            FrameFormat::Hex => unpack_frame(String::from_utf8_lossy(chunk).trim_end(), false),
+            FrameFormat::Nibble => unpack_nibble_frame(chunk, false),
+            FrameFormat::Binary => unpack_binary_frame(chunk, false),
+            FrameFormat::Cobs => unpack_cobs_frame(chunk, false),
+        }
+    }
+}
+
+/// Cuts a received byte stream into frames of one format, keeping a partial
+/// frame across reads.
+#[derive(Debug)]
+pub struct Deframer {
+    format: FrameFormat,
+    buf: Vec<u8>,
+    discarded: u64,
+}
+
+impl Deframer {
+    pub fn new(format: FrameFormat) -> Self {
+        Self {
+            format,
+            buf: Vec::new(),
+            discarded: 0,
+        }
+    }
+
+    /// Bytes skipped while hunting for the start of a frame
+    pub fn discarded(&self) -> u64 {
+        self.discarded
+    }
+
+    /// Bytes received but not yet handed out as (part of) a frame
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Next complete frame (still to be decoded), if one is buffered
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        match self.format.delimiter() {
+            Some(delim) => loop {
+                let end = self.buf.iter().position(|&b| b == delim)?;
+                // A bare delimiter (e.g. idle fill) carries no frame
+                if end == 0 {
+                    self.buf.drain(..1);
+                    self.discarded += 1;
+                    continue;
+                }
+                return Some(self.buf.drain(..=end).collect());
+            },
+            None => loop {
+                // Skip to the next SYNC; a lone first SYNC byte at the end
+                // may still be the start of one
+                let start = self
+                    .buf
+                    .windows(2)
+                    .position(|w| w == BIN_SYNC)
+                    .unwrap_or(self.buf.len().saturating_sub(1));
+                self.buf.drain(..start);
+                self.discarded += start as u64;
+                let len = binary_len(&self.buf)?;
+                if len > MAX_BIN_PAYLOAD {
+                    self.buf.drain(..1);
+                    self.discarded += 1;
+                    continue;
+                }
+                let total = BIN_HEADER_LEN + len + BIN_TRAILER_LEN;
+                if self.buf.len() < total {
+                    return None;
+                }
+                return Some(self.buf.drain(..total).collect());
+            },
+        }
+    }
+
+    /// Hand back a frame that failed to decode. A delimited one is dropped;
+    /// a binary one was cut by a length field that may itself be corrupt
+    /// and so may have swallowed good frames, so the hunt for SYNC resumes
+    /// one byte past where it began. Returns how many of its bytes are gone
+    /// for good; they are a bad frame, not counted as discarded.
+    pub fn reject(&mut self, chunk: Vec<u8>) -> usize {
+        match self.format.delimiter() {
+            Some(_) => chunk.len(),
+            None => {
+                self.buf.splice(..0, chunk.into_iter().skip(1));
+                1
+            }
+        }
+    }
+
+    /// Next frame, reading `port` once if none is buffered yet. Ok(None)
+    /// when the read timed out without completing one.
+    pub fn read_frame<R: Read + ?Sized>(&mut self, port: &mut R) -> io::Result<Option<Vec<u8>>> {
+        if let Some(frame) = self.next_frame() {
+            return Ok(Some(frame));
+        }
+        let mut buf = [0u8; 256];
+        match port.read(&mut buf) {
+            Ok(n) => self.push(&buf[..n]),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::TimedOut
+                        | io::ErrorKind::WouldBlock
+                        | io::ErrorKind::Interrupted
+                ) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(self.next_frame())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::pattern::Pattern;
+
+    fn pay(seq: u64, len: usize) -> Vec<u8> {
+        Pattern::Counter.fill(seq, len)
+    }
+
     #[test]
     fn roundtrip() {
-        let f = build_frame(42, 8);
+        let f = build_frame(42, &pay(42, 8));
         let p = parse_frame(&f).unwrap();
         assert_eq!(p.seq, 42);
+        assert_eq!(p.payload, pay(42, 8));
+    }
+
+    #[test]
+    fn frames_never_contain_xon_xoff() {
+        for seq in 0..300 {
+            // Binary frames carry the full byte range and need XON/XOFF off
+            for format in [FrameFormat::Hex, FrameFormat::Nibble] {
+                let f = format.encode(seq, &pay(seq, 64));
+                assert!(!f.iter().any(|&c| c == 0x11 || c == 0x13), "{:?}", format);
+            }
+        }
+    }
+
+    #[test]
+    fn nibble_roundtrip_fits_in_five_bits() {
+        let f = build_nibble_frame(300, &pay(300, 16));
+        assert!(f.iter().all(|&c| c < 0x20));
+        assert_eq!(f.iter().filter(|&&c| c == NIBBLE_END).count(), 1);
+        let p = parse_nibble_frame(&f).unwrap();
+        assert_eq!(p.seq, 300);
+    }
+
+    #[test]
+    fn nibble_corruption_is_detected() {
+        let mut f = build_nibble_frame(7, &pay(7, 8));
+        f[20] ^= 0x01;
+        assert!(parse_nibble_frame(&f).is_err());
+        // dropped character
+        let mut f = build_nibble_frame(7, &pay(7, 8));
+        f.remove(20);
+        assert!(parse_nibble_frame(&f).is_err());
+    }
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+    }
+
+    #[test]
+    fn binary_roundtrip_and_corruption() {
+        let f = build_binary_frame(70_000, &pay(70_000, 32));
+        assert_eq!(f.len(), BIN_HEADER_LEN + 32 + BIN_TRAILER_LEN);
+        assert_eq!(parse_binary_frame(&f).unwrap().seq, 70_000);
+        for i in BIN_SYNC.len()..f.len() {
+            let mut bad = f.clone();
+            bad[i] ^= 0x10;
+            assert!(parse_binary_frame(&bad).is_err(), "flip at {}", i);
+        }
+    }
+
+    #[test]
+    fn deframer_cuts_binary_frames_and_resyncs() {
+        let mut d = Deframer::new(FrameFormat::Binary);
+        let first = build_binary_frame(1, &pay(1, 8));
+        // Noise (including a stray SYNC byte) ahead of the frame
+        d.push(&[0x00, 0xA5, 0x13]);
+        d.push(&first[..5]);
+        assert!(d.next_frame().is_none());
+        d.push(&first[5..]);
+        d.push(&build_binary_frame(2, &pay(2, 8)));
+        let got: Vec<u64> = std::iter::from_fn(|| d.next_frame())
+            .map(|f| FrameFormat::Binary.decode(&f).unwrap().seq)
+            .collect();
+        assert_eq!(got, [1, 2]);
+
+        // A length field too big to be real is skipped, not waited for
+        d.push(&[0xA5, 0x5A, 0xFF, 0xFF]);
+        d.push(&build_binary_frame(3, &pay(3, 8)));
+        let f = d.next_frame().unwrap();
+        assert_eq!(FrameFormat::Binary.decode(&f).unwrap().seq, 3);
+    }
+
+    #[test]
+    fn rejected_binary_frame_gives_back_the_frames_it_swallowed() {
+        let mut d = Deframer::new(FrameFormat::Binary);
+        let mut bad = build_binary_frame(1, &pay(1, 8));
+        // Length 8 -> 40: the cut runs on over the next two frames
+        bad[3] = 40;
+        d.push(&bad);
+        let good: Vec<Vec<u8>> = (2..5).map(|s| build_binary_frame(s, &pay(s, 8))).collect();
+        good.iter().for_each(|f| d.push(f));
+        let mut got = Vec::new();
+        while let Some(chunk) = d.next_frame() {
+            match FrameFormat::Binary.decode(&chunk) {
+                Ok(f) => got.push(f.seq),
+                Err(_) => {
+                    d.reject(chunk);
+                }
+            }
+        }
+        assert_eq!(got, [2, 3, 4]);
+        // The rest of the bad frame is skipped on the way to the next SYNC
+        assert_eq!(d.discarded(), bad.len() as u64 - 1);
+    }
+
+    #[test]
+    fn deframer_keeps_a_partial_text_frame() {
+        let mut d = Deframer::new(FrameFormat::Hex);
+        let f = FrameFormat::Hex.encode(5, &pay(5, 8));
+        d.push(&f[..10]);
+        assert!(d.next_frame().is_none());
+        d.push(&f[10..]);
+        assert_eq!(
+            FrameFormat::Hex
+                .decode(&d.next_frame().unwrap())
+                .unwrap()
+                .seq,
+            5
+        );
+    }
+
+    #[test]
+    fn cobs_roundtrip_edge_cases() {
+        let long: Vec<u8> = (1..=255).collect();
+        let cases: [&[u8]; 6] = [&[], &[0], &[0, 0], &long[..254], &long, &[1, 0, 2, 0]];
+        for data in cases {
+            let enc = cobs_encode(data);
+            assert!(!enc.contains(&0), "{:?}", data);
+            assert_eq!(cobs_decode(&enc).unwrap(), data);
+        }
+        assert!(cobs_decode(&[3, 1]).is_err());
+        assert!(cobs_decode(&[2, 0]).is_err());
+    }
+
+    #[test]
+    fn frame_tails_have_no_frame_start() {
+        for format in [
+            FrameFormat::Hex,
+            FrameFormat::Nibble,
+            FrameFormat::Binary,
+            FrameFormat::Cobs,
+        ] {
+            let mut f = format.encode(4, &pay(4, 16));
+            assert!(!format.has_frame_start(&f[12..]), "{:?}", format);
+            // Corrupted, but still a whole frame
+            let mid = f.len() / 2;
+            f[mid] ^= 0x02;
+            assert!(format.decode(&f).is_err(), "{:?}", format);
+            assert!(format.has_frame_start(&f), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn cobs_frames_carry_every_byte_value() {
+        let mut seen = [false; 256];
+        for seq in 0..8 {
+            let f = build_cobs_frame(seq, &pay(seq, 32));
+            assert_eq!(f.iter().position(|&b| b == COBS_DELIM), Some(f.len() - 1));
+            assert_eq!(parse_cobs_frame(&f).unwrap().seq, seq);
+            let raw = cobs_decode(&f[..f.len() - 1]).unwrap();
+            raw[6..raw.len() - 4]
+                .iter()
+                .for_each(|&b| seen[b as usize] = true);
+        }
+        assert!(seen.iter().all(|&s| s));
+
+        let mut bad = build_cobs_frame(3, &pay(3, 32));
+        bad[12] ^= 0x04;
+        assert!(parse_cobs_frame(&bad).is_err());
+    }
+
+    #[test]
+    fn unchecked_decode_keeps_corrupted_payloads() {
+        for format in [
+            FrameFormat::Hex,
+            FrameFormat::Nibble,
+            FrameFormat::Binary,
+            FrameFormat::Cobs,
+        ] {
+            let sent = pay(9, 16);
+            let mut f = format.encode(9, &sent);
+            assert_eq!(format.decode(&f).unwrap().payload, sent, "{:?}", format);
+            // Flip one payload bit (a hex digit 0 <-> 1 for text)
+            let at = match format {
+                FrameFormat::Hex => f.windows(4).position(|w| w == b"PAY=").unwrap() + 5,
+                FrameFormat::Nibble => 14,
+                FrameFormat::Binary => BIN_HEADER_LEN,
+                // Stuffing moves it; the counter's first byte is unique
+                FrameFormat::Cobs => f.iter().position(|&b| b == sent[0]).unwrap(),
+            };
+            f[at] ^= 0x01;
+            assert!(format.decode(&f).is_err(), "{:?}", format);
+            let got = format.decode_unchecked(&f).unwrap();
+            assert_eq!(got.seq, 9);
+            let flipped: u32 = sent
+                .iter()
+                .zip(&got.payload)
+                .map(|(a, b)| (a ^ b).count_ones())
+                .sum();
+            assert_eq!(flipped, 1, "{:?}", format);
+        }
     }
 }
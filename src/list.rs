@@ -0,0 +1,471 @@
+use anyhow::Result;
+use serialport::SerialPortType;
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::cli::ListOpts;
+#[cfg(unix)]
+use crate::transport::lock::{PortUser, find_users};
+
+/// Where `list` looks for devices; all overridable so tests can use a fake tree
+#[derive(Debug, Clone)]
+pub struct Roots {
+    pub sys: PathBuf,
+    pub dev: PathBuf,
+    #[cfg_attr(not(unix), allow(dead_code))] // only read for the in-use column
+    pub proc: PathBuf,
+}
+
+impl Roots {
+    fn is_system(&self) -> bool {
+        self.sys == Path::new("/sys") && self.dev == Path::new("/dev")
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsbInfo {
+    pub vid: u16,
+    pub pid: u16,
+    pub serial: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortEntry {
+    pub path: String,
+    /// usb, pci, bluetooth, platform or unknown
+    pub kind: &'static str,
+    pub driver: Option<String>,
+    pub usb: Option<UsbInfo>,
+    pub by_id: Option<String>,
+    /// Processes holding it open, from /proc (unix only)
+    #[cfg(unix)]
+    pub users: Vec<PortUser>,
+}
+
+pub fn run(opts: ListOpts) -> Result<()> {
+    let roots = Roots {
+        sys: opts.sysfs_root.into(),
+        dev: opts.dev_root.into(),
+        proc: opts.proc_root.into(),
+    };
+    let ports = list_ports(&roots)?;
+    if opts.json {
+        println!("{}", to_json(&ports));
+    } else if ports.is_empty() {
+        eprintln!("[list] no serial ports found");
+    } else {
+        for p in &ports {
+            print!("{}", describe(p));
+        }
+    }
+    Ok(())
+}
+
+/// Ports from sysfs, plus whatever `serialport` finds that sysfs did not
+/// (other platforms, Bluetooth RFCOMM), sorted by path.
+pub fn list_ports(roots: &Roots) -> Result<Vec<PortEntry>> {
+    let mut ports = scan_sysfs(roots);
+    // serialport always looks at the real system, so leave it out for a fake tree
+    if roots.is_system() {
+        for info in serialport::available_ports()? {
+            if ports.iter().any(|p| p.path == info.port_name) {
+                continue;
+            }
+            let (kind, usb) = match info.port_type {
+                SerialPortType::UsbPort(u) => (
+                    "usb",
+                    Some(UsbInfo {
+                        vid: u.vid,
+                        pid: u.pid,
+                        serial: u.serial_number,
+                        manufacturer: u.manufacturer,
+                        product: u.product,
+                    }),
+                ),
+                SerialPortType::PciPort => ("pci", None),
+                SerialPortType::BluetoothPort => ("bluetooth", None),
+                SerialPortType::Unknown => ("unknown", None),
+            };
+            ports.push(PortEntry {
+                path: info.port_name,
+                kind,
+                driver: None,
+                usb,
+                by_id: None,
+                #[cfg(unix)]
+                users: Vec::new(),
+            });
+        }
+    }
+
+    let by_id = by_id_links(roots);
+    for p in &mut ports {
+        if p.by_id.is_none() {
+            p.by_id = by_id
+                .iter()
+                .find(|(_, target)| *target == Path::new(&p.path))
+                .map(|(link, _)| link.display().to_string());
+        }
+        #[cfg(unix)]
+        {
+            p.users = find_users(&roots.proc, Path::new(&p.path));
+        }
+    }
+    ports.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(ports)
+}
+
+/// Walk `<sys>/class/tty`, keeping entries backed by a device whose node
+/// exists under `<dev>`.
+pub fn scan_sysfs(roots: &Roots) -> Vec<PortEntry> {
+    let Ok(dir) = fs::read_dir(roots.sys.join("class/tty")) else {
+        return Vec::new();
+    };
+    let mut ports = Vec::new();
+    for entry in dir.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let tty = entry.path();
+        let node = roots.dev.join(&name);
+        if !node.exists() {
+            continue;
+        }
+        if name.starts_with("rfcomm") {
+            ports.push(PortEntry {
+                path: node.display().to_string(),
+                kind: "bluetooth",
+                driver: None,
+                usb: None,
+                by_id: None,
+                #[cfg(unix)]
+                users: Vec::new(),
+            });
+            continue;
+        }
+        // Virtual terminals, ptys and friends have no device
+        let Ok(device) = tty.join("device").canonicalize() else {
+            continue;
+        };
+        // serial8250 registers placeholders for ports that aren't there
+        if read_attr(&tty, "type").as_deref() == Some("0") {
+            continue;
+        }
+        let subsystem = link_name(&device.join("subsystem"));
+        let kind = match subsystem.as_deref() {
+            Some("usb" | "usb-serial") => "usb",
+            Some("pci") => "pci",
+            Some("platform" | "amba" | "pnp" | "serial-base") => "platform",
+            _ => "unknown",
+        };
+        let usb = (kind == "usb").then(|| usb_info(&device)).flatten();
+        ports.push(PortEntry {
+            path: node.display().to_string(),
+            kind,
+            driver: link_name(&device.join("driver")),
+            usb,
+            by_id: None,
+            #[cfg(unix)]
+            users: Vec::new(),
+        });
+    }
+    ports
+}
+
+/// USB descriptor strings live on the first ancestor with an `idVendor`
+fn usb_info(device: &Path) -> Option<UsbInfo> {
+    let usb_dev = device.ancestors().find(|d| d.join("idVendor").is_file())?;
+    let hex = |attr| u16::from_str_radix(&read_attr(usb_dev, attr)?, 16).ok();
+    Some(UsbInfo {
+        vid: hex("idVendor")?,
+        pid: hex("idProduct")?,
+        serial: read_attr(usb_dev, "serial"),
+        manufacturer: read_attr(usb_dev, "manufacturer"),
+        product: read_attr(usb_dev, "product"),
+    })
+}
+
+/// `(link, resolved target)` for every entry in `<dev>/serial/by-id`
+fn by_id_links(roots: &Roots) -> Vec<(PathBuf, PathBuf)> {
+    let Ok(dir) = fs::read_dir(roots.dev.join("serial/by-id")) else {
+        return Vec::new();
+    };
+    dir.flatten()
+        .filter_map(|e| {
+            let link = e.path();
+            let target = link.canonicalize().ok()?;
+            // Report the node under `<dev>` even if `<dev>` itself is a symlink
+            let target = roots.dev.join(target.file_name()?);
+            Some((link, target))
+        })
+        .collect()
+}
+
+/// The `<dev>/serial/by-id` link for `dev`, which keeps naming the same
+/// adapter when it comes back under another ttyUSB number
+pub fn by_id_path(dev_root: &Path, dev: &str) -> Option<PathBuf> {
+    let node = Path::new(dev).canonicalize().ok()?;
+    let roots = Roots {
+        sys: PathBuf::new(),
+        dev: dev_root.into(),
+        proc: PathBuf::new(),
+    };
+    by_id_links(&roots)
+        .into_iter()
+        .find(|(_, target)| target.file_name() == node.file_name())
+        .map(|(link, _)| link)
+}
+
+fn read_attr(dir: &Path, attr: &str) -> Option<String> {
+    let s = fs::read_to_string(dir.join(attr)).ok()?;
+    Some(s.trim().to_string())
+}
+
+fn link_name(link: &Path) -> Option<String> {
+    let target = fs::read_link(link).ok()?;
+    Some(target.file_name()?.to_string_lossy().into_owned())
+}
+
+/// Human-readable block for one port
+pub fn describe(p: &PortEntry) -> String {
+    let mut s = format!("{} [{}]", p.path, p.kind);
+    if let Some(driver) = &p.driver {
+        let _ = write!(s, " driver={}", driver);
+    }
+    s.push('\n');
+    if let Some(usb) = &p.usb {
+        let _ = write!(s, "    usb {:04x}:{:04x}", usb.vid, usb.pid);
+        for (key, val) in [
+            ("manufacturer", &usb.manufacturer),
+            ("product", &usb.product),
+            ("serial", &usb.serial),
+        ] {
+            if let Some(v) = val {
+                let _ = write!(s, " {}=\"{}\"", key, v);
+            }
+        }
+        s.push('\n');
+    }
+    if let Some(by_id) = &p.by_id {
+        let _ = writeln!(s, "    by-id {}", by_id);
+    }
+    #[cfg(unix)]
+    if p.users.is_empty() {
+        s.push_str("    free\n");
+    } else {
+        let users: Vec<String> = p.users.iter().map(|u| u.to_string()).collect();
+        let _ = writeln!(s, "    in use by {}", users.join(", "));
+    }
+    s
+}
+
+pub fn to_json(ports: &[PortEntry]) -> String {
+    let opt = |v: &Option<String>| v.as_deref().map_or("null".to_string(), json_str);
+    let items: Vec<String> = ports
+        .iter()
+        .map(|p| {
+            let usb = p.usb.as_ref().map_or("null".to_string(), |u| {
+                format!(
+                    "{{\"vid\":\"{:04x}\",\"pid\":\"{:04x}\",\"serial\":{},\"manufacturer\":{},\"product\":{}}}",
+                    u.vid,
+                    u.pid,
+                    opt(&u.serial),
+                    opt(&u.manufacturer),
+                    opt(&u.product)
+                )
+            });
+            #[cfg(unix)]
+            let users = {
+                let users: Vec<String> = p
+                    .users
+                    .iter()
+                    .map(|u| format!("{{\"pid\":{},\"comm\":{}}}", u.pid, json_str(&u.comm)))
+                    .collect();
+                format!(
+                    ",\"in_use\":{},\"users\":[{}]",
+                    !p.users.is_empty(),
+                    users.join(",")
+                )
+            };
+            #[cfg(not(unix))]
+            let users = String::new();
+            format!(
+                "{{\"path\":{},\"kind\":{},\"driver\":{},\"usb\":{},\"by_id\":{}{}}}",
+                json_str(&p.path),
+                json_str(p.kind),
+                opt(&p.driver),
+                usb,
+                opt(&p.by_id),
+                users
+            )
+        })
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// A sysfs/dev/proc tree with one FTDI adapter (in use), one PCI UART
+    /// and a serial8250 placeholder.
+    fn fake_tree(tag: &str) -> Roots {
+        let root = std::env::temp_dir().join(format!("hammer-list-{}-{}", tag, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let sys = root.join("sys");
+        let dev = root.join("dev");
+        let proc = root.join("proc");
+        let mkdir = |p: &Path| fs::create_dir_all(p).unwrap();
+        let write = |p: PathBuf, s: &str| fs::write(p, s).unwrap();
+
+        for bus in [
+            "bus/usb-serial/drivers/ftdi_sio",
+            "bus/pci/drivers/exar_serial",
+            "bus/platform/drivers/serial8250",
+        ] {
+            mkdir(&sys.join(bus));
+        }
+        mkdir(&sys.join("class/tty"));
+        mkdir(&dev.join("serial/by-id"));
+
+        // USB device -> interface -> usb-serial port -> tty
+        let usb_dev = sys.join("devices/pci0000:00/usb1/1-2");
+        let port = usb_dev.join("1-2:1.0/ttyUSB0");
+        mkdir(&port);
+        write(usb_dev.join("idVendor"), "0403\n");
+        write(usb_dev.join("idProduct"), "6001\n");
+        write(usb_dev.join("serial"), "A50285BI\n");
+        write(usb_dev.join("manufacturer"), "FTDI\n");
+        write(usb_dev.join("product"), "FT232R \"USB\" UART\n");
+        symlink(sys.join("bus/usb-serial"), port.join("subsystem")).unwrap();
+        symlink(
+            sys.join("bus/usb-serial/drivers/ftdi_sio"),
+            port.join("driver"),
+        )
+        .unwrap();
+        let tty = port.join("tty/ttyUSB0");
+        mkdir(&tty);
+        symlink(&port, tty.join("device")).unwrap();
+        symlink(&tty, sys.join("class/tty/ttyUSB0")).unwrap();
+
+        let pci = sys.join("devices/pci0000:00/0000:00:1c.0");
+        let tty = pci.join("tty/ttyS4");
+        mkdir(&tty);
+        symlink(sys.join("bus/pci"), pci.join("subsystem")).unwrap();
+        symlink(sys.join("bus/pci/drivers/exar_serial"), pci.join("driver")).unwrap();
+        write(tty.join("type"), "4\n");
+        symlink(&pci, tty.join("device")).unwrap();
+        symlink(&tty, sys.join("class/tty/ttyS4")).unwrap();
+
+        let plat = sys.join("devices/platform/serial8250");
+        let tty = plat.join("tty/ttyS31");
+        mkdir(&tty);
+        symlink(sys.join("bus/platform"), plat.join("subsystem")).unwrap();
+        write(tty.join("type"), "0\n");
+        symlink(&plat, tty.join("device")).unwrap();
+        symlink(&tty, sys.join("class/tty/ttyS31")).unwrap();
+
+        // Virtual console: no device
+        mkdir(&sys.join("class/tty/tty0"));
+
+        for node in ["ttyUSB0", "ttyS4", "ttyS31", "tty0"] {
+            write(dev.join(node), "");
+        }
+        symlink(
+            "../../ttyUSB0",
+            dev.join("serial/by-id/usb-FTDI_FT232R_USB_UART_A50285BI-if00-port0"),
+        )
+        .unwrap();
+
+        let fd = proc.join("4242/fd");
+        mkdir(&fd);
+        write(proc.join("4242/comm"), "minicom\n");
+        symlink(dev.join("ttyUSB0"), fd.join("3")).unwrap();
+        mkdir(&proc.join("self"));
+
+        Roots { sys, dev, proc }
+    }
+
+    #[test]
+    fn fake_sysfs_tree_is_listed() {
+        let roots = fake_tree("scan");
+        let ports = list_ports(&roots).unwrap();
+        let paths: Vec<&str> = ports.iter().map(|p| p.path.as_str()).collect();
+        let dev = roots.dev.display().to_string();
+        assert_eq!(paths, [format!("{dev}/ttyS4"), format!("{dev}/ttyUSB0")]);
+
+        let pci = &ports[0];
+        assert_eq!(pci.kind, "pci");
+        assert_eq!(pci.driver.as_deref(), Some("exar_serial"));
+        assert!(pci.usb.is_none() && pci.users.is_empty());
+
+        let usb = &ports[1];
+        assert_eq!(usb.kind, "usb");
+        assert_eq!(usb.driver.as_deref(), Some("ftdi_sio"));
+        let info = usb.usb.as_ref().unwrap();
+        assert_eq!((info.vid, info.pid), (0x0403, 0x6001));
+        assert_eq!(info.serial.as_deref(), Some("A50285BI"));
+        assert_eq!(info.manufacturer.as_deref(), Some("FTDI"));
+        assert!(
+            usb.by_id
+                .as_deref()
+                .unwrap()
+                .ends_with("usb-FTDI_FT232R_USB_UART_A50285BI-if00-port0")
+        );
+        assert_eq!(
+            usb.users,
+            [PortUser {
+                pid: 4242,
+                comm: "minicom".into()
+            }]
+        );
+        let _ = fs::remove_dir_all(roots.sys.parent().unwrap());
+    }
+
+    #[test]
+    fn by_id_link_is_found_for_a_node() {
+        let roots = fake_tree("byid");
+        let node = roots.dev.join("ttyUSB0");
+        let link = by_id_path(&roots.dev, &node.display().to_string()).unwrap();
+        assert!(link.ends_with("usb-FTDI_FT232R_USB_UART_A50285BI-if00-port0"));
+        let pci = roots.dev.join("ttyS4").display().to_string();
+        assert_eq!(by_id_path(&roots.dev, &pci), None);
+        let _ = fs::remove_dir_all(roots.sys.parent().unwrap());
+    }
+
+    #[test]
+    fn json_output_is_escaped() {
+        let roots = fake_tree("json");
+        let json = to_json(&list_ports(&roots).unwrap());
+        assert!(json.starts_with("[{\"path\":"));
+        assert!(json.contains("\"product\":\"FT232R \\\"USB\\\" UART\""));
+        assert!(json.contains("\"vid\":\"0403\",\"pid\":\"6001\""));
+        assert!(json.contains("\"in_use\":true,\"users\":[{\"pid\":4242,\"comm\":\"minicom\"}]"));
+        assert!(json.contains("\"in_use\":false,\"users\":[]"));
+        let _ = fs::remove_dir_all(roots.sys.parent().unwrap());
+    }
+}
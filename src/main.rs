@@ -6,10 +6,14 @@ mod rx;
 mod tx;
 mod port;
 mod frame;
+mod pattern;
+mod list;
 mod stats;
 mod auto;
 mod proto;
+mod selftest;
 mod test;
+mod transport;
 
 fn main() -> Result<()> {
     let args = cli::Cli::parse();
@@ -17,6 +21,8 @@ fn main() -> Result<()> {
         cli::Cmd::Rx(opts) => rx::run(opts),
         cli::Cmd::Tx(opts) => tx::run(opts),
         cli::Cmd::Auto(opts) => auto::run(opts),
-        cli::Cmd::Test(opts) => test::run(opts),
+        cli::Cmd::Test(opts) => test::run(*opts),
+        cli::Cmd::Selftest(opts) => selftest::run(opts),
+        cli::Cmd::List(opts) => list::run(opts),
     }
 }
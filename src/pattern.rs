@@ -0,0 +1,283 @@
+//! Payload generators for data-plane frames.
+//!
+//! Every pattern is a pure function of (seq, len), so the receiver can work
+//! out what any frame should have carried without having seen the ones
+//! before it.
+
+use std::{fmt, str::FromStr, sync::OnceLock};
+
+/// What fills a frame's payload (`--pattern`, `pattern=` in TEST BEGIN)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Pattern {
+    /// Byte counter carried on from frame to frame (`seq * len + i`), so
+    /// every value goes out once per 256 payload bytes. Before patterns were
+    /// selectable each frame restarted at its seq, `(seq + i) & 0xFF`.
+    #[default]
+    Counter,
+    /// ITU-T O.150 pseudo-random bit sequences, one stream running on from
+    /// frame to frame and packed LSB first, the order a UART shifts it out
+    Prbs7,
+    Prbs15,
+    Prbs23,
+    Prbs31,
+    /// 0x55 in every byte: the line toggles on every bit
+    Alt55,
+    /// 0xAA in every byte
+    AltAA,
+    /// All zeros: the longest low run a character can hold
+    Zeros,
+    /// All ones: nothing but start and stop bits toggle
+    Ones,
+    /// A single set bit moving up one place per byte
+    WalkingOnes,
+    /// Pseudo-random bytes from a seed
+    Random(u64),
+}
+
+impl Pattern {
+    /// Payload of frame `seq`, `len` bytes long
+    pub fn fill(self, seq: u64, len: usize) -> Vec<u8> {
+        let offset = seq.wrapping_mul(len as u64);
+        match self {
+            Pattern::Counter => (0..len)
+                .map(|i| offset.wrapping_add(i as u64) as u8)
+                .collect(),
+            Pattern::Prbs7 => PRBS7.fill(seq, len),
+            Pattern::Prbs15 => PRBS15.fill(seq, len),
+            Pattern::Prbs23 => PRBS23.fill(seq, len),
+            Pattern::Prbs31 => PRBS31.fill(seq, len),
+            Pattern::Alt55 => vec![0x55; len],
+            Pattern::AltAA => vec![0xAA; len],
+            Pattern::Zeros => vec![0x00; len],
+            Pattern::Ones => vec![0xFF; len],
+            Pattern::WalkingOnes => (0..len)
+                .map(|i| 1 << (offset.wrapping_add(i as u64) % 8))
+                .collect(),
+            Pattern::Random(seed) => {
+                let mut state = seed ^ mix(seq);
+                let mut out = Vec::with_capacity(len + 8);
+                while out.len() < len {
+                    out.extend_from_slice(&splitmix64(&mut state).to_le_bytes());
+                }
+                out.truncate(len);
+                out
+            }
+        }
+    }
+}
+
+static PRBS7: Lfsr = Lfsr::new(7, 6);
+static PRBS15: Lfsr = Lfsr::new(15, 14);
+static PRBS23: Lfsr = Lfsr::new(23, 18);
+static PRBS31: Lfsr = Lfsr::new(31, 28);
+
+/// Fibonacci LFSR for the PRBS with polynomial x^n + x^k + 1, started from
+/// all ones
+struct Lfsr {
+    n: u32,
+    k: u32,
+    /// `jumps[i][j]`: where bit j of the state ends up after 2^i steps
+    jumps: OnceLock<Vec<Vec<u32>>>,
+}
+
+impl Lfsr {
+    const fn new(n: u32, k: u32) -> Self {
+        Self {
+            n,
+            k,
+            jumps: OnceLock::new(),
+        }
+    }
+
+    fn mask(&self) -> u32 {
+        (1 << self.n) - 1
+    }
+
+    /// Shift once; the bit shifted in is the next bit of the sequence
+    fn step(&self, state: &mut u32) -> u8 {
+        let bit = ((*state >> (self.n - 1)) ^ (*state >> (self.k - 1))) & 1;
+        *state = ((*state << 1) | bit) & self.mask();
+        bit as u8
+    }
+
+    /// `state` after `steps` shifts. Every step is linear over GF(2), so
+    /// this applies the precomputed 2^i-step maps for the bits of `steps`.
+    fn jump(&self, mut state: u32, steps: u64) -> u32 {
+        let jumps = self.jumps.get_or_init(|| {
+            let once: Vec<u32> = (0..self.n)
+                .map(|j| {
+                    let mut s = 1 << j;
+                    self.step(&mut s);
+                    s
+                })
+                .collect();
+            let mut maps = vec![once];
+            for _ in 1..self.n {
+                let last = maps.last().unwrap();
+                let squared = last.iter().map(|&s| apply(last, s)).collect();
+                maps.push(squared);
+            }
+            maps
+        });
+        for (i, map) in jumps.iter().enumerate() {
+            if steps >> i & 1 == 1 {
+                state = apply(map, state);
+            }
+        }
+        state
+    }
+
+    /// Bytes `seq * len` on of the sequence, each filled LSB first
+    fn fill(&self, seq: u64, len: usize) -> Vec<u8> {
+        let period = self.mask() as u128;
+        let offset = (seq as u128 * len as u128 * 8 % period) as u64;
+        let mut state = self.jump(self.mask(), offset);
+        (0..len)
+            .map(|_| (0..8).fold(0u8, |byte, i| byte | self.step(&mut state) << i))
+            .collect()
+    }
+}
+
+/// Image of `state` under the linear map whose columns are `map`
+fn apply(map: &[u32], state: u32) -> u32 {
+    map.iter()
+        .enumerate()
+        .filter(|(j, _)| state >> j & 1 == 1)
+        .fold(0, |acc, (_, &col)| acc ^ col)
+}
+
+/// Scrambles `x` so that neighbouring seqs start far apart
+fn mix(mut x: u64) -> u64 {
+    splitmix64(&mut x)
+}
+
+/// Steps `state` and returns the next output of SplitMix64
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Counter => write!(f, "counter"),
+            Pattern::Prbs7 => write!(f, "prbs7"),
+            Pattern::Prbs15 => write!(f, "prbs15"),
+            Pattern::Prbs23 => write!(f, "prbs23"),
+            Pattern::Prbs31 => write!(f, "prbs31"),
+            Pattern::Alt55 => write!(f, "0x55"),
+            Pattern::AltAA => write!(f, "0xaa"),
+            Pattern::Zeros => write!(f, "zeros"),
+            Pattern::Ones => write!(f, "ones"),
+            Pattern::WalkingOnes => write!(f, "walking-ones"),
+            Pattern::Random(seed) => write!(f, "random:{}", seed),
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        if let Some(seed) = s.strip_prefix("random:") {
+            return seed.parse().map(Pattern::Random).map_err(|_| ());
+        }
+        match s.as_str() {
+            "counter" => Ok(Pattern::Counter),
+            "prbs7" => Ok(Pattern::Prbs7),
+            "prbs15" => Ok(Pattern::Prbs15),
+            "prbs23" => Ok(Pattern::Prbs23),
+            "prbs31" => Ok(Pattern::Prbs31),
+            "0x55" => Ok(Pattern::Alt55),
+            "0xaa" => Ok(Pattern::AltAA),
+            "zeros" => Ok(Pattern::Zeros),
+            "ones" => Ok(Pattern::Ones),
+            "walking-ones" => Ok(Pattern::WalkingOnes),
+            "random" => Ok(Pattern::Random(0)),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Pattern; 11] = [
+        Pattern::Counter,
+        Pattern::Prbs7,
+        Pattern::Prbs15,
+        Pattern::Prbs23,
+        Pattern::Prbs31,
+        Pattern::Alt55,
+        Pattern::AltAA,
+        Pattern::Zeros,
+        Pattern::Ones,
+        Pattern::WalkingOnes,
+        Pattern::Random(42),
+    ];
+
+    #[test]
+    fn names_roundtrip_and_fill_is_deterministic() {
+        for p in ALL {
+            assert_eq!(p.to_string().parse::<Pattern>(), Ok(p));
+            let f = p.fill(7, 33);
+            assert_eq!(f.len(), 33);
+            assert_eq!(f, p.fill(7, 33), "{}", p);
+        }
+        assert_eq!("random".parse::<Pattern>(), Ok(Pattern::Random(0)));
+        assert!("prbs9".parse::<Pattern>().is_err());
+        assert!("random:x".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn prbs7_has_its_full_period_and_longest_runs() {
+        // 127 bytes hold the 127-bit period eight times over
+        let bits: Vec<u8> = Pattern::Prbs7
+            .fill(0, 127)
+            .iter()
+            .flat_map(|b| (0..8).map(move |i| (b >> i) & 1))
+            .collect();
+        assert_eq!(&bits[..127], &bits[127..254]);
+        assert!((1..127).all(|p| bits[..127] != bits[p..p + 127]));
+        // 64 ones and 63 zeros per period, longest runs 7 ones / 6 zeros
+        assert_eq!(bits[..127].iter().filter(|&&b| b == 1).count(), 64);
+        let longest = |v: u8| {
+            bits.chunk_by(|a, b| a == b)
+                .filter(|run| run[0] == v)
+                .map(<[u8]>::len)
+                .max()
+        };
+        assert_eq!(longest(1), Some(7));
+        assert_eq!(longest(0), Some(6));
+    }
+
+    #[test]
+    fn prbs_runs_on_from_frame_to_frame() {
+        for p in [
+            Pattern::Prbs7,
+            Pattern::Prbs15,
+            Pattern::Prbs23,
+            Pattern::Prbs31,
+        ] {
+            let stream = p.fill(0, 16 * 1_001);
+            let frames: Vec<u8> = (0..3).flat_map(|seq| p.fill(seq, 16)).collect();
+            assert_eq!(frames, stream[..48], "{}", p);
+            assert_eq!(p.fill(1_000, 16), stream[16_000..], "{}", p);
+        }
+        // Past the period the jump wraps around: 127 bits in 127 bytes 8 times
+        assert_eq!(Pattern::Prbs7.fill(1, 127), Pattern::Prbs7.fill(0, 127));
+    }
+
+    #[test]
+    fn seeds_and_seqs_change_the_random_payload() {
+        let a = Pattern::Random(1).fill(0, 16);
+        assert_ne!(a, Pattern::Random(2).fill(0, 16));
+        assert_ne!(a, Pattern::Random(1).fill(1, 16));
+        assert_eq!(Pattern::WalkingOnes.fill(1, 3), [0x08, 0x10, 0x20]);
+        assert_eq!(Pattern::Counter.fill(2, 4), [8, 9, 10, 11]);
+    }
+}
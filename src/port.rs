@@ -1,16 +1,20 @@
-use anyhow::{Result, bail};
-use serialport::{DataBits, SerialPort};
+use anyhow::Result;
 use std::{
-    io,
+    path::Path,
     sync::RwLock,
-    sync::atomic::AtomicBool,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
     thread::sleep,
     time::{Duration, Instant},
 };
 
 use crate::{
-    cli::{PortConfig, SerialOpts},
-    proto::command::{FlowControl, Parity},
+    cli::{PortConfig, ReconnectOpts, Rs485Opts, SerialOpts},
+    list::by_id_path,
+    proto::{
+        command::{ConfigMismatch, FlowControl, Parity},
+        parser::{flow_to_str, parity_to_str},
+    },
+    transport::{self, LineReader, OpenOpts, SocketTransport, Transport},
 };
 
 pub const DEFAULT_CONFIG: PortConfig = PortConfig {
@@ -30,6 +34,10 @@ pub static PORT_CONFIG: RwLock<PortConfig> = RwLock::new(PortConfig {
     flow: DEFAULT_CONFIG.flow,
     stop_bits: DEFAULT_CONFIG.stop_bits,
 });
+/// Baud rate read back from the driver after the last retune, 0 = unknown
+pub static PORT_ACTUAL_BAUD: AtomicU32 = AtomicU32::new(0);
+/// Line settings of the control channel (`--control-*`, or found by probing)
+pub static CONTROL_CONFIG: RwLock<PortConfig> = RwLock::new(DEFAULT_CONFIG);
 
 // Macro definition
 #[macro_export]
@@ -41,97 +49,157 @@ macro_rules! debug_eprintln {
     };
 }
 
-pub fn open_port(opts: &SerialOpts) -> Result<Box<dyn SerialPort>> {
-    let builder = serialport::new(&opts.dev, opts.baud)
-        .timeout(Duration::from_millis(100))
-        .data_bits(DataBits::Eight)
-        .parity(serialport::Parity::None)
-        .stop_bits(serialport::StopBits::One)
-        .flow_control(if opts.rtscts {
-            serialport::FlowControl::Hardware
+pub fn open_port(opts: &SerialOpts) -> Result<Box<dyn Transport>> {
+    let cfg = PortConfig {
+        baud: opts.baud,
+        flow: if opts.rtscts {
+            FlowControl::RtsCts
         } else {
-            serialport::FlowControl::None
-        });
-
-    builder
-        .open()
+            FlowControl::None
+        },
+        ..DEFAULT_CONFIG
+    };
+    let open_opts = OpenOpts {
+        lock_dir: opts.lock.lock_dir(),
+        ..OpenOpts::default()
+    };
+    transport::open(&opts.dev, &cfg, &open_opts)
         .map_err(|e| anyhow::anyhow!("open {}: {}", opts.dev, e))
 }
 
-pub fn retune_for_config(
-    port: &mut dyn serialport::SerialPort,
-    baud: u32,
-    parity: Parity,
-    bits: u8,
-    flow: FlowControl,
-) -> Result<()> {
-    use serialport::{DataBits, FlowControl as SpFlow, Parity as SpParity, StopBits};
+/// Largest baud error still reported as applied: a receiver samples mid-bit,
+/// so a couple of percent either way still frames characters
+pub const BAUD_TOLERANCE: f64 = 0.02;
 
+/// Apply `new_cfg`, read it back from the driver and return whatever it did
+/// not take as asked (empty when the backend can't read its config back).
+pub fn retune_for_config(
+    port: &mut dyn Transport,
+    new_cfg: &PortConfig,
+) -> Result<Vec<ConfigMismatch>> {
     // Reset port to known state
     port.set_timeout(Duration::from_millis(100))?;
     port.flush()?;
-    port.clear(serialport::ClearBuffer::All)?;
+    port.clear()?;
 
     // Apply new settings
-    port.set_baud_rate(baud)?;
-    port.set_data_bits(match bits {
-        7 => DataBits::Seven,
-        8 => DataBits::Eight,
-        other => bail!("unsupported data bits: {}", other),
-    })?;
-    port.set_parity(match parity {
-        Parity::None => SpParity::None,
-        Parity::Even => SpParity::Even,
-        Parity::Odd => SpParity::Odd,
-    })?;
-    port.set_stop_bits(StopBits::One)?; // spec: only 1 stop bit
-    port.set_flow_control(match flow {
-        FlowControl::None => SpFlow::None,
-        FlowControl::RtsCts => SpFlow::Hardware,
-    })?;
+    port.retune(new_cfg)?;
 
     // Flush and wait a bit to let things settle
-    port.clear(serialport::ClearBuffer::All)?;
+    port.clear()?;
     sleep(Duration::from_millis(10)); // let settle
 
+    // Ask the driver what it really applied (may round odd rates, drop CRTSCTS)
+    let applied = port.applied_config()?;
+    let mismatch = applied.map_or_else(Vec::new, |got| config_mismatches(new_cfg, &got));
+
     // Update global config
     let mut cfg = PORT_CONFIG.write().unwrap();
-    cfg.baud = baud;
-    cfg.parity = parity;
-    cfg.bits = match bits {
-        7 => 7,
-        8 => 8,
-        other => bail!("unsupported data bits: {}", other),
-    };
-    cfg.flow = flow;
+    *cfg = *new_cfg;
     drop(cfg); // release lock
+    let actual = applied.map(|a| a.baud);
+    PORT_ACTUAL_BAUD.store(actual.unwrap_or(0), Ordering::Relaxed);
 
     eprintln!(
-        "[port] reconfigured to {} {}-{}-{}-{}",
-        baud,
-        bits,
-        match parity {
+        "[port] reconfigured to {}{} {}-{}-{}-{}{}",
+        new_cfg.baud,
+        match actual {
+            Some(a) => format!(" (actual {a})"),
+            None => String::new(),
+        },
+        new_cfg.bits,
+        match new_cfg.parity {
             Parity::None => "N",
             Parity::Even => "E",
             Parity::Odd => "O",
         },
-        1, // stop bits
-        match flow {
+        new_cfg.stop_bits,
+        match new_cfg.flow {
             FlowControl::None => "",
             FlowControl::RtsCts => " +RTS/CTS",
-        }
+            FlowControl::XonXoff => " +XON/XOFF",
+        },
+        format_mismatch(&mismatch)
     );
+    Ok(mismatch)
+}
+
+/// Try `cfg` on the driver and go back to `restore`, without touching the
+/// global config. Used to answer CONFIG SET before actually switching.
+pub fn probe_config(
+    port: &mut dyn Transport,
+    cfg: &PortConfig,
+    restore: &PortConfig,
+) -> Result<Vec<ConfigMismatch>> {
+    port.retune(cfg)?;
+    let applied = port.applied_config();
+    port.retune(restore)?;
+    Ok(applied?.map_or_else(Vec::new, |got| config_mismatches(cfg, &got)))
+}
+
+/// Fields of `got` that differ from `want`; the baud rate only past `BAUD_TOLERANCE`
+pub fn config_mismatches(want: &PortConfig, got: &PortConfig) -> Vec<ConfigMismatch> {
+    let mut out = Vec::new();
+    let mut push = |field: &str, got: String| {
+        out.push(ConfigMismatch {
+            field: field.to_string(),
+            got,
+        })
+    };
+    let baud_err = (got.baud as f64 - want.baud as f64).abs() / want.baud.max(1) as f64;
+    if baud_err > BAUD_TOLERANCE {
+        push("baud", got.baud.to_string());
+    }
+    if got.parity != want.parity {
+        push("parity", parity_to_str(got.parity).to_string());
+    }
+    if got.bits != want.bits {
+        push("bits", got.bits.to_string());
+    }
+    if got.stop_bits != want.stop_bits {
+        push("stop", got.stop_bits.to_string());
+    }
+    if got.flow != want.flow {
+        push("flow", flow_to_str(got.flow).to_string());
+    }
+    out
+}
+
+/// ` MISMATCH(flow:none,...)` for log lines, empty if there is none
+pub fn format_mismatch(mismatch: &[ConfigMismatch]) -> String {
+    if mismatch.is_empty() {
+        return String::new();
+    }
+    let list: Vec<String> = mismatch.iter().map(|m| m.to_string()).collect();
+    format!(" MISMATCH({})", list.join(","))
+}
+
+/// Apply `--rs485` (if given) to a freshly opened port
+pub fn enable_rs485(port: &mut dyn Transport, opts: &Rs485Opts) -> Result<()> {
+    if !opts.rs485 {
+        return Ok(());
+    }
+    port.set_rs485(opts)
+        .map_err(|e| anyhow::anyhow!("enabling RS-485 mode: {}", e))?;
+    eprintln!(
+        "[port] RS-485 mode on (RTS {} ms before send, {} ms after send)",
+        opts.rs485_delay_before_ms, opts.rs485_delay_after_ms
+    );
+    Ok(())
+}
+
+/// Go back to the control channel settings
+pub fn port_default_config(port: &mut dyn Transport) -> Result<()> {
+    retune_for_config(port, &get_control_config())?;
     Ok(())
 }
 
-pub fn port_default_config(port: &mut dyn serialport::SerialPort) -> Result<()> {
-    retune_for_config(
-        port,
-        DEFAULT_CONFIG.baud,
-        DEFAULT_CONFIG.parity,
-        DEFAULT_CONFIG.bits,
-        DEFAULT_CONFIG.flow,
-    )
+pub fn get_control_config() -> PortConfig {
+    *CONTROL_CONFIG.read().unwrap()
+}
+
+pub fn set_control_config(cfg: PortConfig) {
+    *CONTROL_CONFIG.write().unwrap() = cfg;
 }
 
 pub fn get_port_config() -> PortConfig {
@@ -141,84 +209,205 @@ pub fn get_port_config() -> PortConfig {
     result
 }
 
-/// Open the *control channel* (always 115200, 8N1, no flow)
-pub fn open_control(dev: &str) -> Result<Box<dyn SerialPort>> {
-    let builder = serialport::new(dev, 115_200)
-        .timeout(Duration::from_millis(100))
-        .data_bits(DataBits::Eight)
-        .parity(serialport::Parity::None)
-        .stop_bits(serialport::StopBits::One)
-        .flow_control(serialport::FlowControl::None);
+/// Baud rate the driver reported after the last retune, if known
+pub fn get_actual_baud() -> Option<u32> {
+    match PORT_ACTUAL_BAUD.load(Ordering::Relaxed) {
+        0 => None,
+        n => Some(n),
+    }
+}
 
-    builder
-        .open()
+/// Open the *control channel* (115200 8N1 no flow unless `--control-*` say otherwise)
+pub fn open_control(dev: &str, opts: &OpenOpts) -> Result<Box<dyn Transport>> {
+    transport::open(dev, &get_control_config(), opts)
         .map_err(|e| anyhow::anyhow!("open control {}: {}", dev, e))
 }
 
-/// Write a line (string must already have \r\n)
-pub fn write_line(port: &mut dyn SerialPort, line: &str) -> Result<()> {
-    debug_eprintln!("[port] write_line: {}", line.trim_end());
-    port.write_all(line.as_bytes())?;
-    port.flush()?;
-    Ok(())
+/// Path to reopen `dev` by after a disconnect: with `--reconnect-by-id` its
+/// `/dev/serial/by-id` link, looked up now while the device is still there
+pub fn reconnect_path(dev: &str, opts: &ReconnectOpts) -> String {
+    if !opts.reconnect_by_id || dev.contains("/serial/by-id/") {
+        return dev.to_string();
+    }
+    match by_id_path(Path::new("/dev"), dev) {
+        Some(link) => {
+            eprintln!("[port] will reopen {} as {}", dev, link.display());
+            link.display().to_string()
+        }
+        None => {
+            eprintln!(
+                "[port] warning: {} has no /dev/serial/by-id link, reopening by name",
+                dev
+            );
+            dev.to_string()
+        }
+    }
 }
 
-/// Read a CRLF-terminated line without changing the port's timeout.
-///
-/// Behavior:
-/// - Ok(Some(line)) → a full line (CRLF trimmed) was read
-/// - Ok(None)       → no full line available yet (WouldBlock, TimedOut, Ok(0))
-/// - Err(e)         → unexpected I/O error
-fn read_crlf_line(port: &mut dyn serialport::SerialPort) -> Result<Option<String>> {
-    let mut buf = [0u8; 1];
-    let mut line = Vec::new();
-
+/// Call `open` until it works, waiting 250 ms after the first failure and
+/// doubling up to `--reconnect-max-ms`. Never gives up: a soak run would
+/// rather wait for the adapter than stop.
+pub fn reopen_with_backoff<T>(
+    dev: &str,
+    opts: &ReconnectOpts,
+    mut open: impl FnMut() -> Result<T>,
+) -> T {
+    let mut backoff = Duration::from_millis(250);
+    let max = Duration::from_millis(opts.reconnect_max_ms).max(backoff);
     loop {
-        match port.read(&mut buf) {
-            Ok(0) => {
-                // No data: on some backends this can mean "nothing available right now".
-                // Treat like a soft timeout for this attempt.
-                return Ok(None);
-            }
-            Ok(1) => {
-                line.push(buf[0]);
-
-                // Fast-path CRLF check without allocating a String every byte
-                let n = line.len();
-                if n >= 2 && line[n - 2] == b'\r' && line[n - 1] == b'\n' {
-                    // Trim trailing CRLF
-                    line.truncate(n - 2);
-                    // Lossy match original behavior (keeps you safe on bad utf8)
-                    let out = String::from_utf8_lossy(&line).into_owned();
-                    return Ok(Some(out));
-                }
-
-                // Keep reading until we hit CRLF or the OS times us out.
-                continue;
+        match open() {
+            Ok(t) => {
+                eprintln!("[port] reopened {}", dev);
+                return t;
             }
             Err(e) => {
-                // We know `e` is an io::Error
-                let kind = e.kind();
-                match kind {
-                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
-                        return Ok(None);
-                    }
-                    io::ErrorKind::Interrupted => continue,
-                    _ => return Err(e.into()), // promote to anyhow::Error
-                }
+                eprintln!(
+                    "[port] reopening {} failed ({:#}), retrying in {:?}",
+                    dev, e, backoff
+                );
+                sleep(backoff);
+                backoff = (backoff * 2).min(max);
             }
-            _ => return Ok(None), // should not happen
         }
     }
 }
 
+/// Where control lines and data frames go.
+///
+/// In band (`--dev` only) both are handles on the same link, which is
+/// retuned back to the control settings between configs. At 5 or 6 data
+/// bits it also goes back to them for every handshake, as control lines
+/// don't survive the data config. Out of band (`--control-dev`) the control
+/// link keeps its settings for the whole run and only the data link is
+/// retuned.
+pub struct Channels {
+    pub ctrl: LineReader,
+    pub data: Box<dyn Transport>,
+    out_of_band: bool,
+}
+
+impl Channels {
+    /// Data reads from a clone of the control reader, so it gets any frame
+    /// bytes the control side read past its last line
+    pub fn in_band(port: Box<dyn Transport>) -> Result<Self> {
+        let ctrl = LineReader::new(port);
+        Ok(Self {
+            data: ctrl.try_clone()?,
+            ctrl,
+            out_of_band: false,
+        })
+    }
+
+    pub fn out_of_band(ctrl: Box<dyn Transport>, data: Box<dyn Transport>) -> Self {
+        Self {
+            ctrl: LineReader::new(ctrl),
+            data,
+            out_of_band: true,
+        }
+    }
+
+    pub fn is_out_of_band(&self) -> bool {
+        self.out_of_band
+    }
+
+    /// In band, a data config too narrow for control lines (5 or 6 bits)
+    /// is only on the link while frames are
+    fn handshake_on_control(&self) -> bool {
+        !self.out_of_band && get_port_config().bits < 7
+    }
+
+    /// Settings `data` is on between tests, for a probe to go back to
+    pub fn idle_config(&self) -> PortConfig {
+        if self.handshake_on_control() {
+            get_control_config()
+        } else {
+            get_port_config()
+        }
+    }
+
+    /// Put the link on the data config for the frames of a test, if the
+    /// handshake ran on the control settings. The peer switches at about
+    /// the same moment, so give it time to get there before sending.
+    pub fn enter_data(&mut self) -> Result<()> {
+        if self.handshake_on_control() {
+            self.data.retune(&get_port_config())?;
+            self.data.clear()?;
+            sleep(Duration::from_millis(100)); // let the peer switch too
+        }
+        Ok(())
+    }
+
+    /// Back to the control settings for the handshake after the frames (or
+    /// right after CONFIG SET), keeping PORT_CONFIG on the data config
+    pub fn leave_data(&mut self) -> Result<()> {
+        if self.handshake_on_control() {
+            self.data.flush()?;
+            self.data.retune(&get_control_config())?;
+            self.data.clear()?;
+        }
+        Ok(())
+    }
+
+    /// Make sure the control link runs the control settings
+    pub fn reset_control(&mut self) -> Result<()> {
+        if self.out_of_band {
+            // The data config is tracked in PORT_CONFIG, leave it alone
+            self.ctrl.retune(&get_control_config())
+        } else {
+            port_default_config(&mut *self.data)
+        }
+    }
+}
+
+/// Open `dev` for data and `control_dev` (if any) for control.
+///
+/// A `tcp://` or `unix://` control device is a socket of our own: the auto
+/// side (`listen`) binds it and waits for the master to connect.
+pub fn open_channels(
+    dev: &str,
+    control_dev: Option<&str>,
+    opts: &OpenOpts,
+    listen: bool,
+) -> Result<Channels> {
+    let Some(control_dev) = control_dev else {
+        return Channels::in_band(open_control(dev, opts)?);
+    };
+    let mut data = transport::open(dev, &DEFAULT_CONFIG, opts)
+        .map_err(|e| anyhow::anyhow!("open data {}: {}", dev, e))?;
+    retune_for_config(&mut *data, &DEFAULT_CONFIG)?;
+    let ctrl: Box<dyn Transport> = if SocketTransport::is_socket(control_dev) {
+        let socket = if listen {
+            SocketTransport::listen(control_dev, opts.timeout)
+        } else {
+            SocketTransport::connect(control_dev, opts.timeout)
+        };
+        Box::new(socket.map_err(|e| anyhow::anyhow!("open control {}: {}", control_dev, e))?)
+    } else {
+        open_control(control_dev, opts)?
+    };
+    Ok(Channels::out_of_band(ctrl, data))
+}
+
+/// Write a line (string must already have \r\n)
+pub fn write_line(port: &mut dyn Transport, line: &str) -> Result<()> {
+    debug_eprintln!("[port] write_line: {}", line.trim_end());
+    port.write_all(line.as_bytes())?;
+    port.flush()?;
+    Ok(())
+}
+
+/// `wait_for_command` saw no matching line within its timeout
+#[derive(Debug, thiserror::Error)]
+#[error("timed out after {0:?}")]
+pub struct Timeout(pub Duration);
+
 /// Wait for a line that your matcher accepts, with an overall deadline.
 /// - `timeout = Some(d)` → enforce total time limit across many reads
 /// - `timeout = None`    → wait indefinitely
 ///
 /// `matcher` examines each full line; return `Some(T)` to accept, `None` to keep waiting.
 pub fn wait_for_command<T, F>(
-    port: &mut dyn serialport::SerialPort,
+    port: &mut LineReader,
     timeout: Option<Duration>,
     mut matcher: F,
 ) -> Result<T>
@@ -231,11 +420,11 @@ where
         if let Some(limit) = timeout
             && start.elapsed() >= limit
         {
-            bail!("timed out after {:?}", limit);
+            return Err(Timeout(limit).into());
         }
 
         // Try to read *one* line within the remaining window.
-        match read_crlf_line(port)? {
+        match port.read_line()? {
             Some(line) => {
                 if let Some(hit) = matcher(&line) {
                     debug_eprintln!("[port] matched line: {}", line);
@@ -250,3 +439,62 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::loopback::{self, LinkModel};
+
+    #[test]
+    fn small_baud_error_is_tolerated() {
+        let want = PortConfig {
+            baud: 250_000,
+            ..DEFAULT_CONFIG
+        };
+        let rounded = PortConfig {
+            baud: 245_000,
+            ..want
+        };
+        assert!(config_mismatches(&want, &rounded).is_empty());
+        let wrong = PortConfig {
+            baud: 230_400,
+            parity: Parity::Even,
+            ..want
+        };
+        let got: Vec<String> = config_mismatches(&want, &wrong)
+            .iter()
+            .map(|m| m.to_string())
+            .collect();
+        assert_eq!(got, ["baud:230400", "parity:even"]);
+    }
+
+    #[test]
+    fn probe_catches_dropped_crtscts() {
+        let (mut a, _b) = loopback::pair(LinkModel {
+            no_crtscts: true,
+            ..LinkModel::default()
+        });
+        let rtscts = PortConfig {
+            flow: FlowControl::RtsCts,
+            ..DEFAULT_CONFIG
+        };
+        let mismatch = probe_config(&mut a, &rtscts, &DEFAULT_CONFIG).unwrap();
+        assert_eq!(format_mismatch(&mismatch), " MISMATCH(flow:none)");
+        // Left as it was
+        assert_eq!(a.applied_config().unwrap().unwrap().flow, FlowControl::None);
+        assert!(
+            probe_config(&mut a, &DEFAULT_CONFIG, &DEFAULT_CONFIG)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn a_quiet_peer_is_a_timeout() {
+        let (a, _b) = loopback::pair(LinkModel::default());
+        let mut a = LineReader::new(Box::new(a));
+        let err =
+            wait_for_command(&mut a, Some(Duration::from_millis(50)), |_| Some(())).unwrap_err();
+        assert!(err.downcast_ref::<Timeout>().is_some(), "{err}");
+    }
+}
@@ -1,5 +1,7 @@
 use std::fmt;
 
+use crate::{pattern::Pattern, stats::DriverCounters};
+
 #[derive(Debug, Clone)]
 pub enum CtrlCommand {
     // ---- Discovery ----
@@ -16,14 +18,18 @@ pub enum CtrlCommand {
         baud: u32,
         parity: Parity,
         bits: u8,
+        stop_bits: u8,
         flow: FlowControl,
+        strict: bool, // don't apply the config if the driver won't do it as asked
     },
     ConfigSetAck {
         id: String,
         baud: u32,
         parity: Parity,
         bits: u8,
+        stop_bits: u8,
         flow: FlowControl,
+        mismatch: Vec<ConfigMismatch>, // what the driver applied differently
     },
 
     // ---- Test orchestration ----
@@ -34,6 +40,9 @@ pub enum CtrlCommand {
         duration_ms: Option<u64>,
         payload: usize,
         dir: Direction,
+        break_ms: Option<u64>, // break test only
+        format: FrameEncoding, // sent only when not text
+        pattern: Pattern,      // sent only when not counter
     },
     TestBeginAck {
         id: String,
@@ -42,6 +51,9 @@ pub enum CtrlCommand {
         duration_ms: Option<u64>,
         payload: usize,
         dir: Direction,
+        break_ms: Option<u64>,
+        format: FrameEncoding,
+        pattern: Pattern,
     },
 
     TestDone {
@@ -54,7 +66,13 @@ pub enum CtrlCommand {
         lost: u64,
         total: u64,
         duration_micros: u64,
-        bytes: u64, // Bytes sent / received TODO
+        bytes: u64,                     // Bytes sent / received TODO
+        driver: Option<DriverCounters>, // TIOCGICOUNT deltas, if the port has them
+        turnaround_us: Option<u64>,     // shortest ping-to-reply time (turnaround test)
+        breaks: Option<u64>,            // breaks sent / detected (break test)
+        discarded: u64,                 // bytes dropped resyncing on frame boundaries
+        bits_compared: u64,             // payload bits checked against the pattern
+        bit_errors: u64,                // ... and found flipped
     },
 
     TestResult {
@@ -70,6 +88,22 @@ pub enum CtrlCommand {
         reason: Option<String>,
     },
 
+    // ---- Modem control lines (modem-lines test) ----
+    /// Sender has driven its outputs to these levels, peer please sample
+    LinesSet {
+        id: String,
+        dtr: bool,
+        rts: bool,
+    },
+    /// Input levels the peer sampled in reply to `LinesSet`
+    LinesState {
+        id: String,
+        cts: bool,
+        dsr: bool,
+        cd: bool,
+        ri: bool,
+    },
+
     // ---- Terminate ----
     Terminate {
         id: String,
@@ -83,6 +117,12 @@ pub enum CtrlCommand {
 pub enum TestName {
     MaxRate,
     FifoResidue,
+    /// Half-duplex reply latency (RS-485)
+    Turnaround,
+    /// DTR/RTS outputs reach the peer's DSR/CTS inputs
+    ModemLines,
+    /// Break is generated, detected, and the link recovers after it
+    Break,
 }
 
 impl fmt::Display for TestName {
@@ -90,6 +130,9 @@ impl fmt::Display for TestName {
         match self {
             TestName::MaxRate => write!(f, "max-rate"),
             TestName::FifoResidue => write!(f, "fifo-residue"),
+            TestName::Turnaround => write!(f, "turnaround"),
+            TestName::ModemLines => write!(f, "modem-lines"),
+            TestName::Break => write!(f, "break"),
         }
     }
 }
@@ -101,17 +144,46 @@ pub enum Direction {
     Both,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// How the data plane encodes its frames (`format=` in TEST BEGIN)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrameEncoding {
+    /// `@@ SEQ=… ##` hex text, or nibbles below 7 data bits
+    #[default]
+    Text,
+    /// Length-prefixed binary with CRC-32C
+    Binary,
+    /// Zero-delimited COBS binary with CRC-32C
+    Cobs,
+}
+
+/// A line setting the driver applied differently from what was asked,
+/// e.g. `flow:none` when CRTSCTS was silently dropped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigMismatch {
+    /// baud, parity, bits, stop or flow
+    pub field: String,
+    /// What the driver reports instead
+    pub got: String,
+}
+
+impl fmt::Display for ConfigMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.field, self.got)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Parity {
     None,
     Even,
     Odd,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FlowControl {
     None,
     RtsCts,
+    XonXoff,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -3,7 +3,11 @@ use std::{collections::BTreeMap, str::FromStr};
 
 use thiserror::Error;
 
-use super::command::{CtrlCommand, Direction, FlowControl, Parity, TestName, TestResultFlag};
+use super::command::{
+    ConfigMismatch, CtrlCommand, Direction, FlowControl, FrameEncoding, Parity, TestName,
+    TestResultFlag,
+};
+use crate::{pattern::Pattern, stats::DriverCounters};
 
 #[derive(Debug, Error)]
 pub enum ParseError {
@@ -56,28 +60,41 @@ pub fn format_command(cmd: &CtrlCommand) -> String {
             baud,
             parity,
             bits,
+            stop_bits,
             flow,
+            strict,
         } => {
             out.push_str("CONFIG SET");
             push_pair!("id", id);
             push_pair!("baud", baud);
             push_pair!("parity", parity_to_str(*parity));
             push_pair!("bits", bits);
+            push_pair!("stop", stop_bits);
             push_pair!("flow", flow_to_str(*flow));
+            if *strict {
+                push_pair!("strict", 1);
+            }
         }
         ConfigSetAck {
             id,
             baud,
             parity,
             bits,
+            stop_bits,
             flow,
+            mismatch,
         } => {
             out.push_str("CONFIG SET ACK");
             push_pair!("id", id);
             push_pair!("baud", baud);
             push_pair!("parity", parity_to_str(*parity));
             push_pair!("bits", bits);
+            push_pair!("stop", stop_bits);
             push_pair!("flow", flow_to_str(*flow));
+            if !mismatch.is_empty() {
+                let list: Vec<String> = mismatch.iter().map(|m| m.to_string()).collect();
+                push_pair!("mismatch", list.join(","));
+            }
         }
 
         // ---- Test orchestration
@@ -88,6 +105,9 @@ pub fn format_command(cmd: &CtrlCommand) -> String {
             duration_ms,
             dir,
             payload,
+            break_ms,
+            format,
+            pattern,
         } => {
             out.push_str("TEST BEGIN");
             push_pair!("id", id);
@@ -100,6 +120,15 @@ pub fn format_command(cmd: &CtrlCommand) -> String {
             }
             push_pair!("payload", payload);
             push_pair!("dir", direction_to_str(*dir));
+            if let Some(b) = break_ms {
+                push_pair!("break_ms", b);
+            }
+            if *format != FrameEncoding::Text {
+                push_pair!("format", encoding_to_str(*format));
+            }
+            if *pattern != Pattern::Counter {
+                push_pair!("pattern", pattern);
+            }
         }
         TestBeginAck {
             id,
@@ -108,6 +137,9 @@ pub fn format_command(cmd: &CtrlCommand) -> String {
             duration_ms,
             payload,
             dir,
+            break_ms,
+            format,
+            pattern,
         } => {
             out.push_str("TEST BEGIN ACK");
             push_pair!("id", id);
@@ -120,6 +152,15 @@ pub fn format_command(cmd: &CtrlCommand) -> String {
             }
             push_pair!("payload", payload);
             push_pair!("dir", direction_to_str(*dir));
+            if let Some(b) = break_ms {
+                push_pair!("break_ms", b);
+            }
+            if *format != FrameEncoding::Text {
+                push_pair!("format", encoding_to_str(*format));
+            }
+            if *pattern != Pattern::Counter {
+                push_pair!("pattern", pattern);
+            }
         }
 
         TestDone { id } => {
@@ -134,6 +175,12 @@ pub fn format_command(cmd: &CtrlCommand) -> String {
             lost,
             duration_micros,
             bytes,
+            driver,
+            turnaround_us,
+            breaks,
+            discarded,
+            bits_compared,
+            bit_errors,
         } => {
             out.push_str("TEST DONE ACK");
             push_pair!("id", id);
@@ -143,6 +190,22 @@ pub fn format_command(cmd: &CtrlCommand) -> String {
             push_pair!("total", total);
             push_pair!("dur_mcrs", duration_micros);
             push_pair!("bytes", bytes);
+            if let Some(d) = driver {
+                push_driver(&mut out, d);
+            }
+            if let Some(t) = turnaround_us {
+                push_pair!("ta_us", t);
+            }
+            if let Some(b) = breaks {
+                push_pair!("breaks", b);
+            }
+            if *discarded > 0 {
+                push_pair!("discarded", discarded);
+            }
+            if *bits_compared > 0 {
+                push_pair!("bits", bits_compared);
+                push_pair!("bit_errs", bit_errors);
+            }
         }
 
         TestResult {
@@ -174,6 +237,28 @@ pub fn format_command(cmd: &CtrlCommand) -> String {
             }
         }
 
+        // ---- Modem control lines
+        LinesSet { id, dtr, rts } => {
+            out.push_str("LINES SET");
+            push_pair!("id", id);
+            push_pair!("dtr", *dtr as u8);
+            push_pair!("rts", *rts as u8);
+        }
+        LinesState {
+            id,
+            cts,
+            dsr,
+            cd,
+            ri,
+        } => {
+            out.push_str("LINES STATE");
+            push_pair!("id", id);
+            push_pair!("cts", *cts as u8);
+            push_pair!("dsr", *dsr as u8);
+            push_pair!("cd", *cd as u8);
+            push_pair!("ri", *ri as u8);
+        }
+
         // ---- Terminate
         Terminate { id } => {
             out.push_str("TERMINATE");
@@ -237,14 +322,18 @@ pub fn parse_command(line: &str) -> Result<CtrlCommand, ParseError> {
             baud: req_u32(&map, "baud")?,
             parity: req_parity(&map, "parity")?,
             bits: req_u8(&map, "bits")?,
+            stop_bits: opt_u8(&map, "stop")?.unwrap_or(1),
             flow: req_flow(&map, "flow")?,
+            strict: map.contains_key("strict") && req_bool(&map, "strict")?,
         }),
         "CONFIG SET ACK" => Ok(ConfigSetAck {
             id: req_s(&map, "id")?.to_string(),
             baud: req_u32(&map, "baud")?,
             parity: req_parity(&map, "parity")?,
             bits: req_u8(&map, "bits")?,
+            stop_bits: opt_u8(&map, "stop")?.unwrap_or(1),
             flow: req_flow(&map, "flow")?,
+            mismatch: opt_mismatch(&map)?,
         }),
 
         // ---- Test orchestration
@@ -263,6 +352,9 @@ pub fn parse_command(line: &str) -> Result<CtrlCommand, ParseError> {
                 duration_ms,
                 payload: req_usize(&map, "payload")?,
                 dir: req_dir(&map, "dir")?,
+                break_ms: opt_u64(&map, "break_ms")?,
+                format: opt_encoding(&map, "format")?,
+                pattern: opt_pattern(&map, "pattern")?,
             })
         }
         "TEST BEGIN ACK" => {
@@ -275,6 +367,9 @@ pub fn parse_command(line: &str) -> Result<CtrlCommand, ParseError> {
                 duration_ms,
                 payload: req_usize(&map, "payload")?,
                 dir: req_dir(&map, "dir")?,
+                break_ms: opt_u64(&map, "break_ms")?,
+                format: opt_encoding(&map, "format")?,
+                pattern: opt_pattern(&map, "pattern")?,
             })
         }
 
@@ -289,6 +384,12 @@ pub fn parse_command(line: &str) -> Result<CtrlCommand, ParseError> {
             total: req_u64(&map, "total")?,
             duration_micros: req_u64(&map, "dur_mcrs")?,
             bytes: req_u64(&map, "bytes")?,
+            driver: opt_driver(&map)?,
+            turnaround_us: opt_u64(&map, "ta_us")?,
+            breaks: opt_u64(&map, "breaks")?,
+            discarded: opt_u64(&map, "discarded")?.unwrap_or(0),
+            bits_compared: opt_u64(&map, "bits")?.unwrap_or(0),
+            bit_errors: opt_u64(&map, "bit_errs")?.unwrap_or(0),
         }),
 
         "TEST RESULT" => Ok(TestResult {
@@ -307,6 +408,20 @@ pub fn parse_command(line: &str) -> Result<CtrlCommand, ParseError> {
                 .filter(|s| !s.is_empty()),
         }),
 
+        // ---- Modem control lines
+        "LINES SET" => Ok(LinesSet {
+            id: req_s(&map, "id")?.to_string(),
+            dtr: req_bool(&map, "dtr")?,
+            rts: req_bool(&map, "rts")?,
+        }),
+        "LINES STATE" => Ok(LinesState {
+            id: req_s(&map, "id")?.to_string(),
+            cts: req_bool(&map, "cts")?,
+            dsr: req_bool(&map, "dsr")?,
+            cd: req_bool(&map, "cd")?,
+            ri: req_bool(&map, "ri")?,
+        }),
+
         // ---- Terminate
         "TERMINATE" => Ok(Terminate {
             id: req_s(&map, "id")?.to_string(),
@@ -321,6 +436,51 @@ pub fn parse_command(line: &str) -> Result<CtrlCommand, ParseError> {
 
 /* ---------- helpers ---------- */
 
+/// Driver counters travel as `overrun= frame_err= parity_err= brk= buf_overrun=`
+fn push_driver(out: &mut String, d: &DriverCounters) {
+    for (k, v) in [
+        ("overrun", d.overrun),
+        ("frame_err", d.frame),
+        ("parity_err", d.parity),
+        ("brk", d.brk),
+        ("buf_overrun", d.buf_overrun),
+    ] {
+        out.push_str(&format!(" {k}={v}"));
+    }
+}
+
+/// Present only if the sender's port has counters (`overrun` key is there)
+fn opt_driver(map: &BTreeMap<String, String>) -> Result<Option<DriverCounters>, ParseError> {
+    if !map.contains_key("overrun") {
+        return Ok(None);
+    }
+    Ok(Some(DriverCounters {
+        overrun: req_u32(map, "overrun")?,
+        frame: opt_u32(map, "frame_err")?.unwrap_or(0),
+        parity: opt_u32(map, "parity_err")?.unwrap_or(0),
+        brk: opt_u32(map, "brk")?.unwrap_or(0),
+        buf_overrun: opt_u32(map, "buf_overrun")?.unwrap_or(0),
+    }))
+}
+
+/// `mismatch=flow:none,baud:245000`; absent means the config was applied as asked
+fn opt_mismatch(map: &BTreeMap<String, String>) -> Result<Vec<ConfigMismatch>, ParseError> {
+    let Some(v) = map.get("mismatch") else {
+        return Ok(Vec::new());
+    };
+    v.split(',')
+        .map(|item| {
+            let (field, got) = item
+                .split_once(':')
+                .ok_or_else(|| ParseError::BadPair(item.to_string()))?;
+            Ok(ConfigMismatch {
+                field: field.to_string(),
+                got: got.to_string(),
+            })
+        })
+        .collect()
+}
+
 fn req_s<'a>(map: &'a BTreeMap<String, String>, k: &'static str) -> Result<&'a str, ParseError> {
     map.get(k)
         .map(|s| s.as_str())
@@ -352,6 +512,24 @@ fn req_usize(map: &BTreeMap<String, String>, k: &'static str) -> Result<usize, P
     })
 }
 
+fn opt_u8(map: &BTreeMap<String, String>, k: &'static str) -> Result<Option<u8>, ParseError> {
+    Ok(match map.get(k) {
+        None => None,
+        Some(v) => Some(
+            v.parse::<u8>()
+                .map_err(|_| ParseError::BadInt(k, v.clone()))?,
+        ),
+    })
+}
+fn opt_u32(map: &BTreeMap<String, String>, k: &'static str) -> Result<Option<u32>, ParseError> {
+    Ok(match map.get(k) {
+        None => None,
+        Some(v) => Some(
+            v.parse::<u32>()
+                .map_err(|_| ParseError::BadInt(k, v.clone()))?,
+        ),
+    })
+}
 fn opt_u64(map: &BTreeMap<String, String>, k: &'static str) -> Result<Option<u64>, ParseError> {
     Ok(match map.get(k) {
         None => None,
@@ -362,6 +540,15 @@ fn opt_u64(map: &BTreeMap<String, String>, k: &'static str) -> Result<Option<u64
     })
 }
 
+fn req_bool(map: &BTreeMap<String, String>, k: &'static str) -> Result<bool, ParseError> {
+    match map.get(k).map(String::as_str) {
+        None => Err(ParseError::MissingField(k)),
+        Some("0") => Ok(false),
+        Some("1") => Ok(true),
+        Some(v) => Err(ParseError::BadEnum(k, v.to_string())),
+    }
+}
+
 fn req_parity(map: &BTreeMap<String, String>, k: &'static str) -> Result<Parity, ParseError> {
     map.get(k)
         .ok_or(ParseError::MissingField(k))
@@ -382,6 +569,21 @@ fn req_testname(map: &BTreeMap<String, String>, k: &'static str) -> Result<TestN
         .ok_or(ParseError::MissingField(k))
         .and_then(|v| TestName::from_str(v).map_err(|_| ParseError::BadEnum(k, v.clone())))
 }
+fn opt_encoding(
+    map: &BTreeMap<String, String>,
+    k: &'static str,
+) -> Result<FrameEncoding, ParseError> {
+    match map.get(k) {
+        None => Ok(FrameEncoding::Text),
+        Some(v) => FrameEncoding::from_str(v).map_err(|_| ParseError::BadEnum(k, v.clone())),
+    }
+}
+fn opt_pattern(map: &BTreeMap<String, String>, k: &'static str) -> Result<Pattern, ParseError> {
+    match map.get(k) {
+        None => Ok(Pattern::Counter),
+        Some(v) => Pattern::from_str(v).map_err(|_| ParseError::BadEnum(k, v.clone())),
+    }
+}
 fn req_resultflag(
     map: &BTreeMap<String, String>,
     k: &'static str,
@@ -393,7 +595,7 @@ fn req_resultflag(
 
 /* ---------- enum string helpers & FromStr impls ---------- */
 
-fn parity_to_str(p: Parity) -> &'static str {
+pub fn parity_to_str(p: Parity) -> &'static str {
     match p {
         Parity::None => "none",
         Parity::Even => "even",
@@ -407,16 +609,27 @@ fn direction_to_str(d: Direction) -> &'static str {
         Direction::Both => "both",
     }
 }
-fn flow_to_str(f: FlowControl) -> &'static str {
+pub fn flow_to_str(f: FlowControl) -> &'static str {
     match f {
         FlowControl::None => "none",
         FlowControl::RtsCts => "rtscts",
+        FlowControl::XonXoff => "xonxoff",
     }
 }
 fn testname_to_str(t: TestName) -> &'static str {
     match t {
         TestName::MaxRate => "max-rate",
         TestName::FifoResidue => "fifo-residue",
+        TestName::Turnaround => "turnaround",
+        TestName::ModemLines => "modem-lines",
+        TestName::Break => "break",
+    }
+}
+pub fn encoding_to_str(e: FrameEncoding) -> &'static str {
+    match e {
+        FrameEncoding::Text => "text",
+        FrameEncoding::Binary => "bin",
+        FrameEncoding::Cobs => "cobs",
     }
 }
 fn resultflag_to_str(r: TestResultFlag) -> &'static str {
@@ -456,6 +669,7 @@ impl FromStr for FlowControl {
         match s.to_ascii_lowercase().as_str() {
             "none" => Ok(FlowControl::None),
             "rtscts" => Ok(FlowControl::RtsCts),
+            "xonxoff" => Ok(FlowControl::XonXoff),
             _ => Err(()),
         }
     }
@@ -466,6 +680,20 @@ impl FromStr for TestName {
         match s.to_ascii_lowercase().as_str() {
             "max-rate" => Ok(TestName::MaxRate),
             "fifo-residue" => Ok(TestName::FifoResidue),
+            "turnaround" => Ok(TestName::Turnaround),
+            "modem-lines" => Ok(TestName::ModemLines),
+            "break" => Ok(TestName::Break),
+            _ => Err(()),
+        }
+    }
+}
+impl FromStr for FrameEncoding {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(FrameEncoding::Text),
+            "bin" => Ok(FrameEncoding::Binary),
+            "cobs" => Ok(FrameEncoding::Cobs),
             _ => Err(()),
         }
     }
@@ -510,10 +738,13 @@ mod tests {
             baud: 115200,
             parity: Parity::None,
             bits: 8,
+            stop_bits: 2,
             flow: FlowControl::None,
+            strict: false,
         };
         let line = format_command(&cmd);
         assert!(line.ends_with("\r\n"));
+        assert!(!line.contains("strict"));
         let parsed = parse_command(&line).unwrap();
         match parsed {
             CtrlCommand::ConfigSet {
@@ -521,18 +752,88 @@ mod tests {
                 baud,
                 parity,
                 bits,
+                stop_bits,
                 flow,
+                strict,
             } => {
                 assert_eq!(id, "m1");
+                assert!(!strict);
                 assert_eq!(baud, 115200);
                 assert!(matches!(parity, Parity::None));
                 assert_eq!(bits, 8);
+                assert_eq!(stop_bits, 2);
                 assert!(matches!(flow, FlowControl::None));
             }
             _ => panic!("wrong variant"),
         }
     }
 
+    #[test]
+    fn roundtrip_flow_xonxoff() {
+        let cmd = CtrlCommand::ConfigSetAck {
+            id: "a2".into(),
+            baud: 9600,
+            parity: Parity::None,
+            bits: 8,
+            stop_bits: 1,
+            flow: FlowControl::XonXoff,
+            mismatch: Vec::new(),
+        };
+        let line = format_command(&cmd);
+        assert!(line.contains(" flow=xonxoff"));
+        match parse_command(&line).unwrap() {
+            CtrlCommand::ConfigSetAck { flow, .. } => {
+                assert!(matches!(flow, FlowControl::XonXoff))
+            }
+            _ => panic!("wrong variant"),
+        }
+    }
+
+    #[test]
+    fn roundtrip_config_mismatch() {
+        let line =
+            "CONFIG SET id=m1 baud=250000 parity=none bits=8 stop=1 flow=rtscts strict=1\r\n";
+        match parse_command(line).unwrap() {
+            CtrlCommand::ConfigSet { strict, .. } => assert!(strict),
+            _ => panic!("wrong variant"),
+        }
+
+        let mismatch = vec![
+            ConfigMismatch {
+                field: "baud".into(),
+                got: "245000".into(),
+            },
+            ConfigMismatch {
+                field: "flow".into(),
+                got: "none".into(),
+            },
+        ];
+        let cmd = CtrlCommand::ConfigSetAck {
+            id: "a1".into(),
+            baud: 250000,
+            parity: Parity::None,
+            bits: 8,
+            stop_bits: 1,
+            flow: FlowControl::RtsCts,
+            mismatch: mismatch.clone(),
+        };
+        let line = format_command(&cmd);
+        assert!(line.contains(" mismatch=baud:245000,flow:none"));
+        match parse_command(&line).unwrap() {
+            CtrlCommand::ConfigSetAck { mismatch: got, .. } => assert_eq!(got, mismatch),
+            _ => panic!("wrong variant"),
+        }
+    }
+
+    #[test]
+    fn config_set_stop_defaults_to_one() {
+        let line = "CONFIG SET ACK id=a1 baud=9600 parity=even bits=7 flow=none\r\n";
+        match parse_command(line).unwrap() {
+            CtrlCommand::ConfigSetAck { stop_bits, .. } => assert_eq!(stop_bits, 1),
+            _ => panic!("wrong variant"),
+        }
+    }
+
     #[test]
     fn parse_test_begin_frames() {
         let line = "TEST BEGIN id=aa name=max-rate frames=100 payload=128 dir=both\r\n";
@@ -545,8 +846,14 @@ mod tests {
                 duration_ms,
                 payload,
                 dir,
+                break_ms,
+                format,
+                pattern,
             } => {
                 assert_eq!(id, "aa");
+                assert_eq!(pattern, Pattern::Counter);
+                assert_eq!(break_ms, None);
+                assert_eq!(format, FrameEncoding::Text);
                 assert!(matches!(name, TestName::MaxRate));
                 assert_eq!(frames, Some(100));
                 assert_eq!(duration_ms, None);
@@ -580,6 +887,154 @@ mod tests {
         }
     }
 
+    #[test]
+    fn roundtrip_test_done_ack_driver_counters() {
+        let driver = DriverCounters {
+            overrun: 2,
+            frame: 7,
+            parity: 1,
+            brk: 0,
+            buf_overrun: 3,
+        };
+        let cmd = CtrlCommand::TestDoneAck {
+            id: "s2".into(),
+            ok: 10,
+            bad: 1,
+            lost: 0,
+            total: 11,
+            duration_micros: 5_000,
+            bytes: 1_024,
+            driver: Some(driver),
+            turnaround_us: None,
+            breaks: None,
+            discarded: 37,
+            bits_compared: 81_920,
+            bit_errors: 3,
+        };
+        let line = format_command(&cmd);
+        assert!(line.contains(" overrun=2 frame_err=7 parity_err=1 brk=0 buf_overrun=3"));
+        assert!(line.contains(" discarded=37"));
+        assert!(line.contains(" bits=81920 bit_errs=3"));
+        match parse_command(&line).unwrap() {
+            CtrlCommand::TestDoneAck {
+                driver: d,
+                discarded,
+                bits_compared,
+                bit_errors,
+                ..
+            } => {
+                assert_eq!(d, Some(driver));
+                assert_eq!(discarded, 37);
+                assert_eq!((bits_compared, bit_errors), (81_920, 3));
+            }
+            _ => panic!("wrong variant"),
+        }
+
+        // Ports without counters leave the fields out entirely
+        let line = "TEST DONE ACK id=s2 ok=1 bad=0 lost=0 total=1 dur_mcrs=10 bytes=8\r\n";
+        match parse_command(line).unwrap() {
+            CtrlCommand::TestDoneAck {
+                driver,
+                discarded,
+                bits_compared,
+                ..
+            } => {
+                assert_eq!(driver, None);
+                assert_eq!(discarded, 0);
+                assert_eq!(bits_compared, 0);
+            }
+            _ => panic!("wrong variant"),
+        }
+    }
+
+    #[test]
+    fn roundtrip_lines() {
+        let cmd = CtrlCommand::LinesSet {
+            id: "m1".into(),
+            dtr: true,
+            rts: false,
+        };
+        let line = format_command(&cmd);
+        assert_eq!(line, "LINES SET id=m1 dtr=1 rts=0\r\n");
+        match parse_command(&line).unwrap() {
+            CtrlCommand::LinesSet { dtr, rts, .. } => assert!(dtr && !rts),
+            _ => panic!("wrong variant"),
+        }
+
+        let line = "LINES STATE id=a1 cts=0 dsr=1 cd=1 ri=0\r\n";
+        match parse_command(line).unwrap() {
+            CtrlCommand::LinesState {
+                cts, dsr, cd, ri, ..
+            } => assert!(!cts && dsr && cd && !ri),
+            _ => panic!("wrong variant"),
+        }
+        assert!(parse_command("LINES SET id=m1 dtr=yes rts=0").is_err());
+    }
+
+    #[test]
+    fn roundtrip_test_begin_break_ms() {
+        let cmd = CtrlCommand::TestBegin {
+            id: "aa".into(),
+            name: TestName::Break,
+            frames: Some(3),
+            duration_ms: None,
+            payload: 16,
+            dir: Direction::Tx,
+            break_ms: Some(250),
+            format: FrameEncoding::Text,
+            pattern: Pattern::Counter,
+        };
+        let line = format_command(&cmd);
+        assert!(line.contains(" name=break ") && line.contains(" break_ms=250"));
+        match parse_command(&line).unwrap() {
+            CtrlCommand::TestBegin { name, break_ms, .. } => {
+                assert!(matches!(name, TestName::Break));
+                assert_eq!(break_ms, Some(250));
+            }
+            _ => panic!("wrong variant"),
+        }
+    }
+
+    #[test]
+    fn roundtrip_test_begin_format() {
+        let cmd = CtrlCommand::TestBeginAck {
+            id: "aa".into(),
+            name: TestName::MaxRate,
+            frames: Some(3),
+            duration_ms: None,
+            payload: 16,
+            dir: Direction::Rx,
+            break_ms: None,
+            format: FrameEncoding::Binary,
+            pattern: Pattern::Random(7),
+        };
+        let line = format_command(&cmd);
+        assert!(line.contains(" format=bin") && line.contains(" pattern=random:7"));
+        match parse_command(&line).unwrap() {
+            CtrlCommand::TestBeginAck {
+                format, pattern, ..
+            } => {
+                assert_eq!(format, FrameEncoding::Binary);
+                assert_eq!(pattern, Pattern::Random(7));
+            }
+            _ => panic!("wrong variant"),
+        }
+        // Absent means text and counter, so older peers keep working
+        match parse_command("TEST BEGIN id=m1 name=max-rate frames=1 payload=8 dir=tx").unwrap() {
+            CtrlCommand::TestBegin {
+                format, pattern, ..
+            } => {
+                assert_eq!(format, FrameEncoding::Text);
+                assert_eq!(pattern, Pattern::Counter);
+            }
+            _ => panic!("wrong variant"),
+        }
+        assert!(
+            parse_command("TEST BEGIN id=m1 name=max-rate frames=1 payload=8 dir=tx format=cbor")
+                .is_err()
+        );
+    }
+
     #[test]
     fn roundtrip_hello() {
         let cmd = CtrlCommand::Hello {
@@ -620,6 +1075,7 @@ mod tests {
                 duration_ms,
                 payload,
                 dir,
+                ..
             } => {
                 assert_eq!(id, "bb");
                 assert!(matches!(name, TestName::FifoResidue));
@@ -0,0 +1,132 @@
+use anyhow::{Result, anyhow, bail};
+use clap::{Args, FromArgMatches};
+use std::{
+    sync::{atomic::Ordering, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    auto,
+    cli::{AutoOpts, SelftestOpts, TestOpts},
+    port::{Channels, PORT_DEBUG},
+    test::{self, RunSummary},
+    transport::Transport,
+};
+
+enum Side {
+    Auto(Result<()>),
+    Master(Result<RunSummary>),
+}
+
+/// Run `auto` and `test` against each other over a PTY pair and report whether
+/// the HELLO → CONFIG SET → TEST BEGIN → TEST DONE → TERMINATE flow worked.
+pub fn run(opts: SelftestOpts) -> Result<()> {
+    if opts.debug {
+        PORT_DEBUG.store(true, Ordering::Relaxed);
+    }
+    let (master_end, auto_end) = open_pair()?;
+
+    let auto_opts: AutoOpts = sub_opts(&["--dev", "pty", "--once"])?;
+    let test_opts: TestOpts = sub_opts(&[
+        "--dev",
+        "pty",
+        "--tests",
+        &opts.tests,
+        "--bauds",
+        &opts.bauds,
+        "--bits",
+        &opts.bits,
+        "--dir",
+        &opts.dir,
+        "--payload",
+        &opts.payload.to_string(),
+        "--frames",
+        &opts.frames.to_string(),
+        "--format",
+        &opts.format,
+        "--pattern",
+        &opts.pattern,
+        "--on-mismatch",
+        &opts.on_mismatch,
+    ])?;
+
+    // Keep both ends open until both sides are done, otherwise the master
+    // can see a hangup before it has read the final TERMINATE ACK, or the
+    // auto side one while still draining it.
+    let _auto_keepalive = auto_end.try_clone()?;
+    let _master_keepalive = master_end.try_clone()?;
+
+    let (done_tx, done_rx) = mpsc::channel();
+    let auto_tx = done_tx.clone();
+    thread::spawn(move || {
+        let result =
+            Channels::in_band(auto_end).and_then(|mut ch| auto::serve(&mut ch, &auto_opts));
+        let _ = auto_tx.send(Side::Auto(result));
+    });
+    thread::spawn(move || {
+        let result =
+            Channels::in_band(master_end).and_then(|mut ch| test::run_on(&mut ch, &test_opts));
+        let _ = done_tx.send(Side::Master(result));
+    });
+
+    let deadline = Instant::now() + Duration::from_millis(opts.timeout_ms);
+    let mut auto_result = None;
+    let mut master_result = None;
+    while auto_result.is_none() || master_result.is_none() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match done_rx.recv_timeout(left) {
+            Ok(Side::Auto(r)) => auto_result = Some(r),
+            Ok(Side::Master(r)) => master_result = Some(r),
+            Err(_) => break,
+        }
+    }
+
+    let summary = match master_result {
+        Some(Ok(summary)) => summary,
+        Some(Err(e)) => bail!("[selftest] FAIL: master error: {e:#}"),
+        None => bail!(
+            "[selftest] FAIL: master did not finish within {}ms",
+            opts.timeout_ms
+        ),
+    };
+    match auto_result {
+        Some(Ok(())) => {}
+        Some(Err(e)) => bail!("[selftest] FAIL: auto error: {e:#}"),
+        None => bail!(
+            "[selftest] FAIL: auto did not finish within {}ms",
+            opts.timeout_ms
+        ),
+    }
+    if summary.passed == 0 || summary.failed > 0 {
+        bail!(
+            "[selftest] FAIL: {} passed, {} failed",
+            summary.passed,
+            summary.failed
+        );
+    }
+    eprintln!("[selftest] PASS: {} tests passed", summary.passed);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn open_pair() -> Result<(Box<dyn Transport>, Box<dyn Transport>)> {
+    use crate::transport::SerialTransport;
+
+    let (master, slave) = SerialTransport::pty_pair().map_err(|e| anyhow!("open pty pair: {e}"))?;
+    Ok((Box::new(master), Box::new(slave)))
+}
+
+#[cfg(not(unix))]
+fn open_pair() -> Result<(Box<dyn Transport>, Box<dyn Transport>)> {
+    Err(anyhow!(
+        "selftest needs a PTY pair, which this platform lacks"
+    ))
+}
+
+/// Build subcommand options from CLI-style args so clap defaults apply.
+pub(crate) fn sub_opts<T: Args + FromArgMatches>(args: &[&str]) -> Result<T> {
+    let cmd = T::augment_args(clap::Command::new("selftest").no_binary_name(true));
+    let matches = cmd.try_get_matches_from(args)?;
+    Ok(T::from_arg_matches(&matches)?)
+}
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{fmt, time::Duration};
 
 #[derive(Debug, Clone)]
 pub struct Stats {
@@ -9,6 +9,157 @@ pub struct Stats {
     pub bytes: u64,
     pub bpb: u32,
     pub duration_micros: u64,
+    /// Driver error counters accumulated during the run (if the port has them)
+    pub driver: Option<DriverCounters>,
+    /// Shortest time from a ping leaving to the first byte of its clean
+    /// reply (turnaround test)
+    pub turnaround_us: Option<u64>,
+    /// Breaks sent (TX) or detected (RX) by the break test
+    pub breaks: Option<u64>,
+    /// Received bytes thrown away while resynchronising on frame boundaries
+    pub discarded: u64,
+    /// Payload bits compared against the pattern, and how many were flipped.
+    /// A frame that never arrived counts with all of its bits flipped.
+    pub bits_compared: u64,
+    pub bit_errors: u64,
+}
+
+/// Receive error counters kept by the tty driver (Linux `TIOCGICOUNT`).
+/// Like the kernel's they are 32 bits wide and wrap.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DriverCounters {
+    pub overrun: u32,
+    pub frame: u32,
+    pub parity: u32,
+    pub brk: u32,
+    pub buf_overrun: u32,
+}
+
+impl fmt::Display for DriverCounters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "overrun={} frame={} parity={} brk={} buf_overrun={}",
+            self.overrun, self.frame, self.parity, self.brk, self.buf_overrun
+        )
+    }
+}
+
+// Bits of `TestOutcome::errors`
+pub const ERR_FRAME: u32 = 1 << 0;
+pub const ERR_PARITY: u32 = 1 << 1;
+pub const ERR_BREAK: u32 = 1 << 2;
+pub const ERR_OVERRUN: u32 = 1 << 3;
+pub const ERR_BUF_OVERRUN: u32 = 1 << 4;
+
+impl DriverCounters {
+    /// Counts accumulated since the `before` snapshot, across a wrap
+    pub fn since(&self, before: &Self) -> Self {
+        Self {
+            overrun: self.overrun.wrapping_sub(before.overrun),
+            frame: self.frame.wrapping_sub(before.frame),
+            parity: self.parity.wrapping_sub(before.parity),
+            brk: self.brk.wrapping_sub(before.brk),
+            buf_overrun: self.buf_overrun.wrapping_sub(before.buf_overrun),
+        }
+    }
+
+    /// Hardware FIFO plus tty buffer overruns
+    pub fn overruns(&self) -> u64 {
+        self.overrun as u64 + self.buf_overrun as u64
+    }
+
+    /// Nonzero counters packed as `ERR_*` flags
+    pub fn error_mask(&self) -> u32 {
+        [
+            (self.frame, ERR_FRAME),
+            (self.parity, ERR_PARITY),
+            (self.brk, ERR_BREAK),
+            (self.overrun, ERR_OVERRUN),
+            (self.buf_overrun, ERR_BUF_OVERRUN),
+        ]
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .fold(0, |mask, (_, bit)| mask | bit)
+    }
+}
+
+/// Confidence level BER bounds are quoted at unless `--confidence` says
+pub const DEFAULT_CONFIDENCE: f64 = 0.95;
+
+pub fn ber(bits_compared: u64, bit_errors: u64) -> Option<f64> {
+    (bits_compared > 0).then(|| bit_errors as f64 / bits_compared as f64)
+}
+
+/// Highest BER still consistent, at `confidence` (e.g. 0.95), with seeing
+/// `bit_errors` in `bits_compared` bits. Errors are taken as Poisson
+/// distributed; with none this is -ln(1 - confidence) / bits, i.e. about
+/// 3 / bits at 95%.
+pub fn ber_upper_bound(bits_compared: u64, bit_errors: u64, confidence: f64) -> Option<f64> {
+    if bits_compared == 0 || !(0.0..1.0).contains(&confidence) {
+        return None;
+    }
+    // Mean error count whose chance of producing this few errors is 1 - CL
+    let alpha = 1.0 - confidence;
+    let mut hi = bit_errors as f64 + 1.0;
+    while poisson_cdf(bit_errors, hi) > alpha {
+        hi *= 2.0;
+    }
+    let mut lo = 0.0;
+    for _ in 0..100 {
+        let mid = (lo + hi) / 2.0;
+        if poisson_cdf(bit_errors, mid) > alpha {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    Some(hi / bits_compared as f64)
+}
+
+/// Bits that must pass without an error to claim `target` BER at
+/// `confidence`
+pub fn bits_for_ber(target: f64, confidence: f64) -> u64 {
+    (-(1.0 - confidence).ln() / target).ceil() as u64
+}
+
+/// P(X <= k) for X ~ Poisson(mean), summed from the k-th term down
+fn poisson_cdf(k: u64, mean: f64) -> f64 {
+    if mean <= 0.0 {
+        return 1.0;
+    }
+    let mut ln_term = k as f64 * mean.ln() - mean - ln_factorial(k);
+    let mut sum = 0.0;
+    for i in (0..=k).rev() {
+        let term = ln_term.exp();
+        sum += term;
+        if term < sum * 1e-17 {
+            break;
+        }
+        ln_term += (i as f64).ln() - mean.ln();
+    }
+    sum.min(1.0)
+}
+
+fn ln_factorial(n: u64) -> f64 {
+    if n < 20 {
+        return (2..=n).map(|i| (i as f64).ln()).sum();
+    }
+    // Stirling's series
+    let x = n as f64;
+    x * x.ln() - x + 0.5 * (2.0 * std::f64::consts::PI * x).ln() + 1.0 / (12.0 * x)
+        - 1.0 / (360.0 * x * x * x)
+}
+
+/// Bits that differ between what was sent and what arrived; bytes missing
+/// from either side count as eight errored bits each.
+pub fn bit_errors(expected: &[u8], got: &[u8]) -> u64 {
+    let flipped: u64 = expected
+        .iter()
+        .zip(got)
+        .map(|(a, b)| (a ^ b).count_ones() as u64)
+        .sum();
+    flipped + 8 * expected.len().abs_diff(got.len()) as u64
 }
 
 impl Stats {
@@ -21,6 +172,12 @@ impl Stats {
             bytes: 0,
             bpb,
             duration_micros: 0,
+            driver: None,
+            turnaround_us: None,
+            breaks: None,
+            discarded: 0,
+            bits_compared: 0,
+            bit_errors: 0,
         }
     }
     pub fn add_bytes(&mut self, n: usize) {
@@ -38,6 +195,15 @@ impl Stats {
         self.lost += n;
         self.total += n;
     }
+    pub fn add_bits(&mut self, compared: u64, errors: u64) {
+        self.bits_compared += compared;
+        self.bit_errors += errors;
+    }
+
+    /// Bit error rate over the payloads compared, if any were
+    pub fn ber(&self) -> Option<f64> {
+        ber(self.bits_compared, self.bit_errors)
+    }
 
     pub fn maybe_print(&mut self, stats_int: f64) {
         let dur = Duration::from_micros(self.duration_micros)
@@ -61,3 +227,72 @@ impl Stats {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn driver_counters_delta_and_mask() {
+        let before = DriverCounters {
+            overrun: 3,
+            frame: 1,
+            ..DriverCounters::default()
+        };
+        let after = DriverCounters {
+            overrun: 5,
+            frame: 1,
+            parity: 2,
+            buf_overrun: 1,
+            ..DriverCounters::default()
+        };
+        let d = after.since(&before);
+        assert_eq!(d.overrun, 2);
+        assert_eq!(d.frame, 0);
+        assert_eq!(d.overruns(), 3);
+        assert_eq!(d.error_mask(), ERR_PARITY | ERR_OVERRUN | ERR_BUF_OVERRUN);
+        // The kernel's counters wrap at 32 bits
+        let wrapped = DriverCounters {
+            overrun: 1,
+            ..DriverCounters::default()
+        };
+        let near_wrap = DriverCounters {
+            overrun: u32::MAX - 1,
+            ..DriverCounters::default()
+        };
+        assert_eq!(wrapped.since(&near_wrap).overrun, 3);
+    }
+
+    #[test]
+    fn bit_errors_and_ber() {
+        assert_eq!(bit_errors(&[0x00, 0xFF], &[0x01, 0x7E]), 3);
+        assert_eq!(bit_errors(&[0x00, 0x00], &[0x00]), 8);
+        let mut s = Stats::new(8);
+        assert_eq!(s.ber(), None);
+        s.add_bits(4_000, 0);
+        assert_eq!(s.ber(), Some(0.0));
+        s.add_bits(4_000, 2);
+        assert_eq!(s.ber(), Some(2.5e-4));
+    }
+
+    #[test]
+    fn ber_upper_bounds_match_chi_square_tables() {
+        let close = |got: Option<f64>, want: f64| {
+            let got = got.unwrap();
+            assert!((got - want).abs() <= want * 1e-4, "{} != {}", got, want);
+        };
+        // Zero errors: -ln(1 - CL) / n
+        close(ber_upper_bound(1_000_000_000, 0, 0.95), 2.9957e-9);
+        close(ber_upper_bound(1_000_000_000, 0, 0.99), 4.6052e-9);
+        // chi2(CL; 2k + 2) / 2n
+        close(ber_upper_bound(1_000, 1, 0.95), 4.7439e-3);
+        close(ber_upper_bound(1_000, 2, 0.95), 6.2958e-3);
+        close(ber_upper_bound(1_000_000, 100, 0.95), 1.1808e-4);
+        assert_eq!(ber_upper_bound(0, 0, 0.95), None);
+        assert_eq!(ber_upper_bound(1_000, 0, 1.0), None);
+
+        let bits = bits_for_ber(1e-9, 0.95);
+        assert_eq!(bits, 2_995_732_274);
+        assert!(ber_upper_bound(bits, 0, 0.95).unwrap() <= 1e-9);
+    }
+}
@@ -1,43 +1,105 @@
-use std::{sync::atomic::Ordering, thread::sleep, time::Duration};
+use std::{fmt, sync::atomic::Ordering, thread::sleep, time::Duration};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use uuid::Uuid;
 
 use crate::{
-    cli::PortConfig,
+    cli::{MismatchPolicy, PortConfig},
+    debug_eprintln,
+    frame::{FrameFormat, MAX_BIN_PAYLOAD},
+    pattern::Pattern,
     port::{
-        PORT_DEBUG, open_control, port_default_config, retune_for_config, wait_for_command,
-        write_line,
+        Channels, PORT_DEBUG, Timeout, enable_rs485, format_mismatch, get_actual_baud,
+        get_control_config, open_channels, probe_config, reconnect_path, reopen_with_backoff,
+        retune_for_config, set_control_config, wait_for_command, write_line,
     },
     proto::{
-        command::CtrlCommand,
+        command::{ConfigMismatch, CtrlCommand, Direction, FlowControl, FrameEncoding, TestName},
         parser::{format_command, parse_command},
     },
-    test::{runner::run_hammer_test, test_config::TestConfig},
+    stats::bits_for_ber,
+    test::{
+        runner::{DONE_ACK_TIMEOUT, run_hammer_test},
+        test_config::TestConfig,
+    },
+    transport::{LineReader, Transport, is_disconnect},
 };
 
 pub mod runner;
+pub mod test_break;
 pub mod test_config;
 pub mod test_max_rate;
+pub mod test_modem_lines;
+pub mod test_turnaround;
+
+/// Tally of the tests run by one master session
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RunSummary {
+    pub passed: usize,
+    pub failed: usize,
+    /// Configs cut short because a device went away (`--reconnect`)
+    pub disconnects: usize,
+}
+
+/// How far a master session got through its configs
+#[derive(Debug, Default, Clone, Copy)]
+struct Progress {
+    next_config: usize,
+    summary: RunSummary,
+}
 
 pub fn run(args: crate::cli::TestOpts) -> Result<()> {
     if args.debug {
         PORT_DEBUG.store(true, Ordering::Relaxed);
     }
-    let mut port = open_control(&args.dev)
-        .with_context(|| format!("opening control channel on {}", args.dev))?;
+    set_control_config(args.control.port_config());
+    let reopen_dev = reconnect_path(&args.dev, &args.reconnect);
+    let open = |dev: &str| {
+        open_channels(dev, args.control_dev.as_deref(), &args.open_opts(), false)
+            .with_context(|| format!("opening control channel on {}", dev))
+    };
+    let mut ch = open(&args.dev)?;
 
-    port_default_config(&mut *port)?;
+    let mut progress = Progress::default();
+    loop {
+        enable_rs485(&mut *ch.data, &args.rs485)?;
+        match run_from(&mut ch, &args, &mut progress) {
+            Err(e) if args.reconnect.reconnect && is_disconnect(&e) => {
+                progress.summary.disconnects += 1;
+                eprintln!(
+                    "[test] DISCONNECT: lost the link during config #{} ({e:#}), resuming with the next one",
+                    progress.next_config
+                );
+                // Release the dead handles (and our lock file) first
+                drop(ch);
+                ch = reopen_with_backoff(&reopen_dev, &args.reconnect, || open(&reopen_dev));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Run the master side of the protocol on already opened channels.
+pub fn run_on(ch: &mut Channels, args: &crate::cli::TestOpts) -> Result<RunSummary> {
+    let mut progress = Progress::default();
+    run_from(ch, args, &mut progress)?;
+    Ok(progress.summary)
+}
+
+/// Sync with the auto side, then run the configs from `progress.next_config`
+/// on. A config is counted as done as soon as it starts, so a session that
+/// breaks off resumes with the one after it.
+fn run_from(ch: &mut Channels, args: &crate::cli::TestOpts, progress: &mut Progress) -> Result<()> {
+    let encoding = args.get_frame_encoding()?;
+    let pattern = args.get_pattern()?;
+    let confidence = args.get_confidence()?;
+    let target_ber = args.get_target_ber()?;
+    let ber_frames = target_ber
+        .map(|target| frames_for_ber(args.payload, target, confidence))
+        .transpose()?;
 
     let my_test_id = Uuid::new_v4().to_string();
-    eprintln!("[test] id={} awaiting slave", my_test_id);
-    let _slave_id = wait_for_test_slave_sync(
-        &mut *port,
-        &my_test_id,
-        args.hello_ms,
-        args.hello_backoff_max_ms,
-    )
-    .with_context(|| "waiting for test slave sync")?;
+    sync_with_auto(ch, args, &my_test_id)?;
 
     let port_configs = args.get_port_configs();
 
@@ -46,33 +108,119 @@ pub fn run(args: crate::cli::TestOpts) -> Result<()> {
         n => Some(n as u64),
     };
 
-    for port_config in &port_configs {
-        send_config_set(&mut *port, &my_test_id, port_config)?;
+    let policy = args.get_mismatch_policy();
+    let summary = &mut progress.summary;
+    while let Some(port_config) = port_configs.get(progress.next_config) {
+        progress.next_config += 1;
+        let check = match send_config_set(
+            ch,
+            &my_test_id,
+            port_config,
+            policy != MismatchPolicy::Run,
+            Duration::from_millis(args.repeat_timeout_ms),
+        ) {
+            Ok(check) => check,
+            // No CONFIG SET ACK: most likely the auto side is reopening its
+            // device. Anything else (a local retune failing, say) is an error.
+            Err(e) if args.reconnect.reconnect && e.downcast_ref::<Timeout>().is_some() => {
+                summary.disconnects += 1;
+                eprintln!(
+                    "[test] DISCONNECT: auto side stopped answering at config {:?} ({e:#}), syncing again",
+                    port_config
+                );
+                sync_with_auto(ch, args, &my_test_id)?;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if !check.is_clean() {
+            match policy {
+                MismatchPolicy::Skip => {
+                    eprintln!(
+                        "[test] skipping config {:?}: not supported ({})",
+                        port_config, check
+                    );
+                    continue;
+                }
+                MismatchPolicy::Fail => {
+                    eprintln!(
+                        "[test] config {:?} not supported ({}), failing its tests",
+                        port_config, check
+                    );
+                }
+                MismatchPolicy::Run => {
+                    eprintln!(
+                        "[test] warning: config {:?} not applied as asked ({}), running anyway",
+                        port_config, check
+                    );
+                }
+            }
+        }
 
         let test_names = args.get_test_names();
         for test_name in test_names {
             for dir in args.get_dirs() {
+                if let Some(why) = skip_reason(
+                    args,
+                    test_name,
+                    dir,
+                    port_config,
+                    encoding,
+                    ch.is_out_of_band(),
+                ) {
+                    eprintln!("[test] skipping '{}' dir={:?}: {}", test_name, dir, why);
+                    continue;
+                }
+                if policy == MismatchPolicy::Fail && !check.is_clean() {
+                    eprintln!(
+                        "[test] FAIL '{}' dir={:?}: config not supported",
+                        test_name, dir
+                    );
+                    summary.failed += 1;
+                    continue;
+                }
+                let target_ber = target_ber.filter(|_| matches!(test_name, TestName::MaxRate));
+                let (frames, duration_ms) = match (target_ber, ber_frames) {
+                    (Some(target), Some(n)) => {
+                        ber_test_length(args, port_config, encoding, pattern, target, confidence, n)
+                    }
+                    _ => (frames, args.duration_ms),
+                };
                 eprintln!(
-                    "[test] running test '{}' dir={:?} at {:?} {}bps",
-                    test_name, dir, port_config, port_config.baud
+                    "[test] running test '{}' dir={:?} at {:?} {}bps (actual {})",
+                    test_name,
+                    dir,
+                    port_config,
+                    port_config.baud,
+                    get_actual_baud().map_or_else(|| "unknown".to_string(), |b| b.to_string())
                 );
                 match run_hammer_test(
-                    &mut *port,
+                    ch,
                     &my_test_id,
                     TestConfig {
                         name: test_name,
                         frames,
-                        duration_ms: args.duration_ms,
+                        duration_ms,
                         payload: args.payload,
                         dir,
+                        break_ms: matches!(test_name, TestName::Break).then_some(args.break_ms),
+                        format: encoding,
+                        pattern,
+                        confidence,
+                        target_ber,
                     },
                     true,
+                    // Without --reconnect, wait for the auto side however long
+                    args.reconnect.reconnect.then_some(DONE_ACK_TIMEOUT),
                 )
-                .with_context(|| "running max-rate test")
+                .with_context(|| format!("running {} test", test_name))
                 {
-                    Ok(_) => {}
+                    Ok(Some(outcome)) if outcome.pass => summary.passed += 1,
+                    Ok(_) => summary.failed += 1,
+                    Err(e) if args.reconnect.reconnect && is_disconnect(&e) => return Err(e),
                     Err(e) => {
-                        eprintln!("[test] max-rate test failed: {e}");
+                        eprintln!("[test] {} test failed: {e:#}", test_name);
+                        summary.failed += 1;
                     }
                 };
             }
@@ -80,9 +228,9 @@ pub fn run(args: crate::cli::TestOpts) -> Result<()> {
     }
 
     let terminate = CtrlCommand::Terminate { id: my_test_id };
-    write_line(&mut *port, &format_command(&terminate))?;
+    write_line(&mut ch.ctrl, &format_command(&terminate))?;
     wait_for_command(
-        &mut *port,
+        &mut ch.ctrl,
         Some(Duration::from_millis(5_000)),
         |line: &str| {
             let result = parse_command(line);
@@ -95,73 +243,441 @@ pub fn run(args: crate::cli::TestOpts) -> Result<()> {
         },
     )?;
 
+    eprintln!(
+        "[test] finished: {} passed, {} failed{}",
+        summary.passed,
+        summary.failed,
+        match summary.disconnects {
+            0 => String::new(),
+            n => format!(", {} disconnects", n),
+        }
+    );
     Ok(())
 }
 
+/// HELLO until the auto side answers, on the control settings (switching to
+/// whichever `--control-probe` baud it answered at)
+fn sync_with_auto(
+    ch: &mut Channels,
+    args: &crate::cli::TestOpts,
+    my_test_id: &str,
+) -> Result<String> {
+    ch.reset_control()?;
+    eprintln!("[test] id={} awaiting slave", my_test_id);
+    let (slave_id, control_baud) = wait_for_test_slave_sync(
+        &mut ch.ctrl,
+        my_test_id,
+        &args.get_control_bauds(),
+        args.hello_ms,
+        args.hello_backoff_max_ms,
+    )
+    .with_context(|| "waiting for test slave sync")?;
+    if control_baud != get_control_config().baud {
+        eprintln!(
+            "[test] auto side answered at {} baud, using it for control",
+            control_baud
+        );
+        set_control_config(PortConfig {
+            baud: control_baud,
+            ..get_control_config()
+        });
+        ch.reset_control()?;
+    }
+    Ok(slave_id)
+}
+
+/// Why a test/direction can't run at this config, if it can't
+fn skip_reason(
+    args: &crate::cli::TestOpts,
+    test_name: TestName,
+    dir: Direction,
+    port_config: &PortConfig,
+    encoding: FrameEncoding,
+    out_of_band: bool,
+) -> Option<&'static str> {
+    let both = matches!(dir, Direction::Both);
+    let binary = encoding != FrameEncoding::Text;
+    match test_name {
+        // Full duplex makes no sense on an RS-485 bus
+        _ if both && args.rs485.rs485 => Some("RS-485 is half-duplex"),
+        TestName::Turnaround if both => Some("half-duplex test"),
+        TestName::ModemLines if both => Some("one side drives the lines at a time"),
+        TestName::Break if both => Some("one side sends the break"),
+        // The kernel drives RTS itself with CRTSCTS
+        TestName::ModemLines if matches!(port_config.flow, FlowControl::RtsCts) => {
+            Some("RTS is owned by hardware flow control")
+        }
+        // In band, line states are exchanged as text on the data config
+        TestName::ModemLines if !out_of_band && port_config.bits < 7 => {
+            Some("needs 7 or 8 data bits")
+        }
+        TestName::ModemLines => None,
+        // Binary frames use every byte value, XON/XOFF included
+        _ if binary && port_config.bits < 8 => Some("binary frames need 8 data bits"),
+        _ if binary && matches!(port_config.flow, FlowControl::XonXoff) => {
+            Some("binary frames would trip XON/XOFF")
+        }
+        _ if encoding == FrameEncoding::Binary && args.payload > MAX_BIN_PAYLOAD => {
+            Some("payload too long for binary frames")
+        }
+        _ => None,
+    }
+}
+
+/// Frames of `payload` bytes that carry enough bits to show `target` BER
+/// at `confidence`, provided none of them flip. Frames carry their seq in
+/// 32 bits, so a run that would wrap it is refused.
+fn frames_for_ber(payload: usize, target: f64, confidence: f64) -> Result<u64> {
+    let frames = bits_for_ber(target, confidence).div_ceil(8 * payload.max(1) as u64);
+    if frames > u32::MAX as u64 {
+        bail!(
+            "--target-ber {:.0e} needs {} frames of {} bytes, more than a frame seq can count; raise --payload",
+            target,
+            frames,
+            payload
+        );
+    }
+    Ok(frames)
+}
+
+/// Frames and duration for a max-rate run at `port_config` that moves the
+/// `frames` needed to show `target` BER at `confidence`.
+fn ber_test_length(
+    args: &crate::cli::TestOpts,
+    port_config: &PortConfig,
+    encoding: FrameEncoding,
+    pattern: Pattern,
+    target: f64,
+    confidence: f64,
+    frames: u64,
+) -> (Option<u64>, Option<u64>) {
+    let payload = args.payload.max(1);
+    let frame_len = FrameFormat::for_test(encoding, port_config.bits)
+        .encode(0, &pattern.fill(0, payload))
+        .len();
+    let wire_ms = (frames as f64 * frame_len as f64 * port_config.bits_per_byte() as f64 * 1000.0
+        / port_config.baud as f64) as u64;
+    // TX paces at 99.9% of line rate and needs a moment to get going
+    let duration_ms = wire_ms + wire_ms / 100 + 2_000;
+    eprintln!(
+        "[test] ber<={:.0e} at {}% confidence: {} frames, about {}s at {} baud",
+        target,
+        (confidence * 1000.0).round() / 10.0,
+        frames,
+        duration_ms.div_ceil(1_000),
+        port_config.baud
+    );
+    (Some(frames), Some(duration_ms))
+}
+
+/// Send HELLO until the auto side ACKs; returns its id and the control baud
+/// it answered at. With more than one control baud, each backoff round tries
+/// them all in turn (leaving the port on the one that worked).
 fn wait_for_test_slave_sync(
-    port: &mut dyn serialport::SerialPort,
+    port: &mut LineReader,
     my_id: &str,
+    bauds: &[u32],
     initial_ms: u64,
     max_ms: u64,
-) -> Result<String> {
-    // Ensure port is in default config
-
+) -> Result<(String, u32)> {
+    let probing = bauds.len() > 1;
     let mut backoff = initial_ms.max(200);
     loop {
-        let hello = CtrlCommand::Hello {
-            id: my_id.to_string(),
-        };
-        write_line(port, &format_command(&hello))?;
+        for &baud in bauds {
+            let control = PortConfig {
+                baud,
+                ..get_control_config()
+            };
+            if probing {
+                debug_eprintln!("[test] probing control channel at {}", baud);
+                port.retune(&control)?;
+                port.clear()?;
+                // End whatever the other tries left in the peer's line buffer
+                write_line(port, "\r\n")?;
+            }
+            let hello = CtrlCommand::Hello {
+                id: my_id.to_string(),
+            };
+            write_line(port, &format_command(&hello))?;
 
-        let slave_id =
-            wait_for_command(port, Some(Duration::from_millis(backoff)), |line: &str| {
-                let result = parse_command(line);
-                if let Ok(ref cmd) = result
-                    && let CtrlCommand::Ack { id } = cmd
-                {
-                    eprintln!("[test] got ACK from slave id={}", id);
-                    return Some(id.clone());
-                }
-                None
-            })
-            .ok();
-        if let Some(id) = slave_id {
-            return Ok(id);
+            let slave_id =
+                wait_for_command(port, Some(Duration::from_millis(backoff)), |line: &str| {
+                    let result = parse_command(line);
+                    if let Ok(ref cmd) = result
+                        && let CtrlCommand::Ack { id } = cmd
+                    {
+                        eprintln!("[test] got ACK from slave id={}", id);
+                        return Some(id.clone());
+                    }
+                    None
+                })
+                .ok();
+            if let Some(id) = slave_id {
+                return Ok((id, baud));
+            }
         }
 
         backoff = (backoff.saturating_mul(2)).min(max_ms.max(initial_ms));
     }
 }
 
+/// What the two drivers did not apply as asked for one config
+#[derive(Debug, Default)]
+struct ConfigCheck {
+    local: Vec<ConfigMismatch>,
+    peer: Vec<ConfigMismatch>,
+}
+
+impl ConfigCheck {
+    fn is_clean(&self) -> bool {
+        self.local.is_empty() && self.peer.is_empty()
+    }
+}
+
+impl fmt::Display for ConfigCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if !self.local.is_empty() {
+            parts.push(format!("local{}", format_mismatch(&self.local)));
+        }
+        if !self.peer.is_empty() {
+            parts.push(format!("peer{}", format_mismatch(&self.peer)));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// Agree on `port_config` with the auto side and switch to it.
+///
+/// With `strict`, a config our own driver won't do is not even sent, and
+/// one the peer's driver won't do is not applied on either end, so the
+/// control channel stays on a config both sides really run.
 fn send_config_set(
-    port: &mut dyn serialport::SerialPort,
+    ch: &mut Channels,
     my_id: &str,
     port_config: &PortConfig,
-) -> Result<()> {
+    strict: bool,
+    timeout: Duration,
+) -> Result<ConfigCheck> {
+    let idle = ch.idle_config();
+    let mut check = ConfigCheck {
+        local: probe_config(&mut *ch.data, port_config, &idle)?,
+        ..ConfigCheck::default()
+    };
+    if strict && !check.is_clean() {
+        return Ok(check);
+    }
+
     let config_set = CtrlCommand::ConfigSet {
         id: my_id.to_string(),
         baud: port_config.baud,
         parity: port_config.parity,
         bits: port_config.bits,
+        stop_bits: port_config.stop_bits,
         flow: port_config.flow,
+        strict,
     };
-    write_line(port, &format_command(&config_set))?;
-    wait_for_command(port, Some(Duration::from_millis(10_000)), |line: &str| {
+    write_line(&mut ch.ctrl, &format_command(&config_set))?;
+    check.peer = wait_for_command(&mut ch.ctrl, Some(timeout), |line: &str| {
         let result = parse_command(line);
-        if let Ok(ref cmd) = result
-            && let CtrlCommand::ConfigSetAck { .. } = cmd
-        {
-            return Some(());
+        if let Ok(CtrlCommand::ConfigSetAck { mismatch, .. }) = result {
+            return Some(mismatch);
         }
         None
     })?;
-    retune_for_config(
-        port,
-        port_config.baud,
-        port_config.parity,
-        port_config.bits,
-        port_config.flow,
-    )?;
+    if strict && !check.is_clean() {
+        return Ok(check);
+    }
+    retune_for_config(&mut *ch.data, port_config)?;
+    ch.leave_data()?;
     sleep(Duration::from_millis(100)); // let settle
-    Ok(())
+    Ok(check)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::loopback::{self, LinkModel};
+    use std::{sync::mpsc, thread};
+
+    #[test]
+    fn ber_runs_the_frame_seq_cannot_count_are_refused() {
+        assert_eq!(frames_for_ber(16, 1e-9, 0.95).unwrap(), 23_404_159);
+        assert!(frames_for_ber(16, 1e-12, 0.95).is_err());
+        assert!(frames_for_ber(4_096, 1e-12, 0.95).is_ok());
+        let opts = |extra: &[&str]| {
+            let args = [&["--dev", "x"], extra].concat();
+            crate::selftest::sub_opts::<crate::cli::TestOpts>(&args).unwrap()
+        };
+        assert!(opts(&["--target-ber", "2"]).get_target_ber().is_err());
+        assert!(opts(&["--confidence", "95"]).get_confidence().is_err());
+        assert!(opts(&["--format", "cobbs"]).get_frame_encoding().is_err());
+        assert_eq!(opts(&[]).get_target_ber().unwrap(), None);
+    }
+
+    #[test]
+    fn control_probe_finds_the_auto_baud() {
+        let (master, auto) = loopback::pair(LinkModel::default());
+        let (mut master, mut auto) = (
+            LineReader::new(Box::new(master)),
+            LineReader::new(Box::new(auto)),
+        );
+        let auto_side = thread::spawn(move || {
+            auto.retune(&PortConfig {
+                baud: 57_600,
+                ..get_control_config()
+            })
+            .unwrap();
+            wait_for_command(&mut auto, Some(Duration::from_secs(5)), |line: &str| {
+                matches!(parse_command(line), Ok(CtrlCommand::Hello { .. })).then_some(())
+            })
+            .unwrap();
+            let ack = CtrlCommand::Ack { id: "a1".into() };
+            write_line(&mut auto, &format_command(&ack)).unwrap();
+        });
+        let (done, result) = mpsc::channel();
+        thread::spawn(move || {
+            let bauds = [115_200, 9_600, 57_600];
+            let _ = done.send(wait_for_test_slave_sync(
+                &mut master,
+                "m1",
+                &bauds,
+                200,
+                400,
+            ));
+        });
+        let (id, baud) = result
+            .recv_timeout(Duration::from_secs(10))
+            .unwrap()
+            .unwrap();
+        auto_side.join().unwrap();
+        assert_eq!(id, "a1");
+        assert_eq!(baud, 57_600);
+    }
+
+    #[test]
+    fn out_of_band_control_runs_the_same_suite() {
+        let (master_ctrl, auto_ctrl) = loopback::pair(LinkModel::default());
+        let (master_data, auto_data) = loopback::pair(LinkModel::default());
+        let auto_opts: crate::cli::AutoOpts =
+            crate::selftest::sub_opts(&["--dev", "data", "--control-dev", "ctrl", "--once"])
+                .unwrap();
+        let test_opts: crate::cli::TestOpts = crate::selftest::sub_opts(&[
+            "--dev",
+            "data",
+            "--control-dev",
+            "ctrl",
+            "--bauds",
+            "115200",
+            "--tests",
+            "max-rate",
+            "--frames",
+            "20",
+        ])
+        .unwrap();
+        thread::spawn(move || {
+            let mut ch = Channels::out_of_band(Box::new(auto_ctrl), Box::new(auto_data));
+            crate::auto::serve(&mut ch, &auto_opts)
+        });
+        let (done, result) = mpsc::channel();
+        thread::spawn(move || {
+            let mut ch = Channels::out_of_band(Box::new(master_ctrl), Box::new(master_data));
+            let _ = done.send(run_on(&mut ch, &test_opts));
+        });
+        let summary = result
+            .recv_timeout(Duration::from_secs(30))
+            .unwrap()
+            .unwrap();
+        assert_eq!((summary.passed, summary.failed), (2, 0));
+    }
+
+    #[test]
+    fn master_resumes_with_the_next_config_after_the_auto_side_drops_out() {
+        let (master, auto) = loopback::pair(LinkModel::default());
+        let auto_opts: crate::cli::AutoOpts =
+            crate::selftest::sub_opts(&["--dev", "auto", "--once"]).unwrap();
+        let test_opts: crate::cli::TestOpts = crate::selftest::sub_opts(&[
+            "--dev",
+            "master",
+            "--bauds",
+            "115200",
+            "--parity",
+            "none,even",
+            "--tests",
+            "max-rate",
+            "--dir",
+            "tx",
+            "--frames",
+            "20",
+            "--reconnect",
+            "--repeat-timeout-ms",
+            "500",
+        ])
+        .unwrap();
+        thread::spawn(move || {
+            let mut ch = Channels::in_band(Box::new(auto)).unwrap();
+            // Answer HELLO, then go silent on the first CONFIG SET as if unplugged
+            wait_for_command(&mut ch.ctrl, Some(Duration::from_secs(5)), |line: &str| {
+                matches!(parse_command(line), Ok(CtrlCommand::Hello { .. })).then_some(())
+            })
+            .unwrap();
+            let ack = CtrlCommand::Ack { id: "gone".into() };
+            write_line(&mut ch.ctrl, &format_command(&ack)).unwrap();
+            wait_for_command(&mut ch.ctrl, Some(Duration::from_secs(5)), |line: &str| {
+                matches!(parse_command(line), Ok(CtrlCommand::ConfigSet { .. })).then_some(())
+            })
+            .unwrap();
+            // Back after a replug
+            crate::auto::serve(&mut ch, &auto_opts)
+        });
+        let (done, result) = mpsc::channel();
+        thread::spawn(move || {
+            let mut ch = Channels::in_band(Box::new(master)).unwrap();
+            let _ = done.send(run_on(&mut ch, &test_opts));
+        });
+        let summary = result
+            .recv_timeout(Duration::from_secs(30))
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.disconnects, 1);
+        assert_eq!((summary.passed, summary.failed), (1, 0));
+    }
+    #[test]
+    fn in_band_handshake_survives_five_data_bits() {
+        let (master, auto) = loopback::pair(LinkModel::default());
+        let auto_opts: crate::cli::AutoOpts =
+            crate::selftest::sub_opts(&["--dev", "auto", "--once"]).unwrap();
+        let test_opts: crate::cli::TestOpts = crate::selftest::sub_opts(&[
+            "--dev",
+            "master",
+            "--bauds",
+            "115200",
+            "--bits",
+            "5",
+            "--tests",
+            "max-rate",
+            "--dir",
+            "tx",
+            "--frames",
+            "20",
+            "--on-mismatch",
+            "run",
+        ])
+        .unwrap();
+        let auto_side = thread::spawn(move || {
+            let mut ch = Channels::in_band(Box::new(auto)).unwrap();
+            crate::auto::serve(&mut ch, &auto_opts)
+        });
+        let (done, result) = mpsc::channel();
+        thread::spawn(move || {
+            let mut ch = Channels::in_band(Box::new(master)).unwrap();
+            let _ = done.send(run_on(&mut ch, &test_opts));
+        });
+        let summary = result
+            .recv_timeout(Duration::from_secs(30))
+            .unwrap()
+            .unwrap();
+        assert_eq!((summary.passed, summary.failed), (1, 0));
+        auto_side.join().unwrap().unwrap();
+    }
 }
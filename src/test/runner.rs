@@ -1,27 +1,42 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 
 use crate::{
     auto::dataplane::{TestOutcome},
-    port::{wait_for_command, write_line},
+    debug_eprintln,
+    port::{Channels, wait_for_command, write_line},
     proto::{
-        command::{CtrlCommand, Direction},
+        command::{CtrlCommand, Direction, TestName},
         parser::{format_command, parse_command},
     },
     stats::Stats,
     test::{
+        test_break::{run_break_rx, run_break_tx},
         test_config::TestConfig,
-        test_max_rate::{run_max_rate_rx, run_max_rate_tx},
+        test_max_rate::{add_unreceived_tail, run_max_rate_rx, run_max_rate_tx},
+        test_modem_lines::{run_modem_lines_rx, run_modem_lines_tx},
+        test_turnaround::{run_turnaround_rx, run_turnaround_tx},
     },
+    transport::{LineReader, is_disconnect},
 };
 
+/// Longest the master keeps repeating TEST DONE with `--reconnect`: past
+/// the longest test, so only a peer that went away hits it
+pub const DONE_ACK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Run one test: TEST BEGIN/DONE and their ACKs go over `ch.ctrl`, the
+/// frames over `ch.data` (the same link unless the control channel is out of
+/// band). The master repeats TEST DONE until it is acknowledged, or gives up
+/// after `done_ack_timeout` if one is set.
 pub fn run_hammer_test(
-    port: &mut dyn serialport::SerialPort,
+    ch: &mut Channels,
     my_id: &str,
     conf: TestConfig,
     is_master: bool,
-) -> Result<()> {
+    done_ack_timeout: Option<Duration>,
+) -> Result<Option<TestOutcome>> {
+    let ctrl = &mut ch.ctrl;
     // Auto slave should already be synced and configured
     if is_master {
         let test_cmd = CtrlCommand::TestBegin {
@@ -36,9 +51,12 @@ pub fn run_hammer_test(
                 Direction::Both => Direction::Both,
                 Direction::Rx => Direction::Tx,
             },
+            break_ms: conf.break_ms,
+            format: conf.format,
+            pattern: conf.pattern,
         };
-        write_line(port, &format_command(&test_cmd))?;
-        wait_for_command(port, Some(Duration::from_millis(10_000)), |line: &str| {
+        write_line(ctrl, &format_command(&test_cmd))?;
+        wait_for_command(ctrl, Some(Duration::from_millis(10_000)), |line: &str| {
             let result = parse_command(line);
             if let Ok(ref cmd) = result
                 && let CtrlCommand::TestBeginAck { .. } = cmd
@@ -55,23 +73,30 @@ pub fn run_hammer_test(
             duration_ms: conf.duration_ms,
             payload: conf.payload,
             dir: conf.dir,
+            break_ms: conf.break_ms,
+            format: conf.format,
+            pattern: conf.pattern,
         };
-        write_line(port, &format_command(&ack_cmd))?;
+        write_line(ctrl, &format_command(&ack_cmd))?;
     }
 
-    // TODO handle multiple test types
-    let stats = match conf.dir {
-        Direction::Tx => run_max_rate_tx(port, conf.clone())?,
-        Direction::Rx => run_max_rate_rx(port, conf.clone())?,
-        Direction::Both => Stats::new(8),
-    };
+    // In band, `data` shares the control reader's buffer and starts with these
+    if ctrl.pending() > 0 {
+        debug_eprintln!("[test] {} bytes read past the handshake", ctrl.pending());
+    }
+    // Back on the control settings even if the test failed part way
+    ch.enter_data()?;
+    let frames = run_test_frames(ch, my_id, &conf);
+    ch.leave_data()?;
+    let stats = frames?;
+    let ctrl = &mut ch.ctrl;
 
     let is_ack_mode = is_test_done_ack_mode(conf.dir, true);
     let mut other_stats: Option<Stats> = None;
 
     // Send Done and Ack with stats sharing
     if !is_master {
-        wait_for_command(port, Some(Duration::from_millis(10_000)), |line: &str| {
+        wait_for_command(ctrl, Some(Duration::from_millis(10_000)), |line: &str| {
             if let Ok(cmd) = parse_command(line)
                 && let CtrlCommand::TestDone { .. } = cmd
             {
@@ -87,10 +112,16 @@ pub fn run_hammer_test(
             total: stats.total,
             duration_micros: stats.duration_micros,
             bytes: stats.bytes,
+            driver: stats.driver,
+            turnaround_us: stats.turnaround_us,
+            breaks: stats.breaks,
+            discarded: stats.discarded,
+            bits_compared: stats.bits_compared,
+            bit_errors: stats.bit_errors,
         };
-        write_line(&mut *port, &format_command(&ack))?;
+        write_line(ctrl, &format_command(&ack))?;
     } else {
-        let test_done_ack = wait_for_test_done_ack_sync(&mut *port, my_id, 200, 1_000)?;
+        let test_done_ack = wait_for_test_done_ack_sync(ctrl, my_id, 200, 1_000, done_ack_timeout)?;
         other_stats = if let CtrlCommand::TestDoneAck {
             ok,
             bad,
@@ -98,6 +129,12 @@ pub fn run_hammer_test(
             total,
             duration_micros,
             bytes,
+            driver,
+            turnaround_us,
+            breaks,
+            discarded,
+            bits_compared,
+            bit_errors,
             ..
         } = test_done_ack
         {
@@ -109,22 +146,63 @@ pub fn run_hammer_test(
                 duration_micros,
                 bytes,
                 bpb: stats.bpb,
+                driver,
+                turnaround_us,
+                breaks,
+                discarded,
+                bits_compared,
+                bit_errors,
             })
         } else {
             None
         };
     }
     if is_master && let Some(other_stats) = other_stats {
-        let outcome: TestOutcome = if is_ack_mode {
-            // is_ack_mode = is rx
-            TestOutcome::from_test_stats(other_stats, stats)
+        // is_ack_mode = is rx
+        let (tx_stats, mut rx_stats) = if is_ack_mode {
+            (other_stats, stats)
         } else {
-            TestOutcome::from_test_stats(stats, other_stats)
+            (stats, other_stats)
+        };
+        let outcome = match conf.name {
+            TestName::Turnaround => TestOutcome::from_turnaround_stats(tx_stats, rx_stats),
+            TestName::ModemLines => TestOutcome::from_modem_lines_stats(tx_stats, rx_stats),
+            TestName::Break => TestOutcome::from_break_stats(tx_stats, rx_stats),
+            _ => {
+                add_unreceived_tail(&tx_stats, &mut rx_stats, &conf);
+                let mut outcome = TestOutcome::from_test_stats(tx_stats, rx_stats);
+                outcome.check_ber(conf.confidence, conf.target_ber);
+                outcome
+            }
         };
         outcome.log();
+        return Ok(Some(outcome));
     }
 
-    Ok(())
+    Ok(None)
+}
+
+/// The frame (or line) phase of a test, on the data config
+fn run_test_frames(ch: &mut Channels, my_id: &str, conf: &TestConfig) -> Result<Stats> {
+    let (ctrl, data) = (&mut ch.ctrl, &mut *ch.data);
+    let counters_before = data.error_counters()?;
+    let mut stats = match (conf.name, conf.dir) {
+        (TestName::Turnaround, Direction::Tx) => run_turnaround_tx(data, conf.clone())?,
+        (TestName::Turnaround, Direction::Rx) => run_turnaround_rx(data, conf.clone())?,
+        (TestName::ModemLines, Direction::Tx) => run_modem_lines_tx(ctrl, data, my_id)?,
+        (TestName::ModemLines, Direction::Rx) => run_modem_lines_rx(ctrl, data)?,
+        (TestName::Break, Direction::Tx) => run_break_tx(data, conf.clone())?,
+        (TestName::Break, Direction::Rx) => run_break_rx(data, conf.clone())?,
+        // TODO handle multiple test types
+        (_, Direction::Tx) => run_max_rate_tx(data, conf.clone())?,
+        (_, Direction::Rx) => run_max_rate_rx(data, conf.clone())?,
+        (_, Direction::Both) => Stats::new(8),
+    };
+    stats.driver = match (counters_before, data.error_counters()?) {
+        (Some(before), Some(after)) => Some(after.since(&before)),
+        _ => None,
+    };
+    Ok(stats)
 }
 
 fn is_test_done_ack_mode(dir: Direction, is_master: bool) -> bool {
@@ -136,18 +214,25 @@ fn is_test_done_ack_mode(dir: Direction, is_master: bool) -> bool {
 }
 
 fn wait_for_test_done_ack_sync(
-    port: &mut dyn serialport::SerialPort,
+    port: &mut LineReader,
     my_id: &str,
     initial_ms: u64,
     max_ms: u64,
+    give_up_after: Option<Duration>,
 ) -> Result<CtrlCommand> {
     let mut backoff = initial_ms.max(200);
+    let start = Instant::now();
 
     let cmd = CtrlCommand::TestDone {
         id: my_id.to_string(),
     };
     let line = format_command(&cmd);
     loop {
+        if let Some(limit) = give_up_after
+            && start.elapsed() >= limit
+        {
+            bail!("no TEST DONE ACK after {:?}", limit);
+        }
         write_line(port, &line)?;
 
         let test_done_ack =
@@ -159,10 +244,11 @@ fn wait_for_test_done_ack_sync(
                     return Some(cmd.clone());
                 }
                 None
-            })
-            .ok();
-        if let Some(test_done_ack) = test_done_ack {
-            return Ok(test_done_ack);
+            });
+        match test_done_ack {
+            Ok(test_done_ack) => return Ok(test_done_ack),
+            Err(e) if is_disconnect(&e) => return Err(e),
+            Err(_) => {}
         }
 
         backoff = (backoff.saturating_mul(2)).min(max_ms.max(initial_ms));
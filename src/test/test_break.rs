@@ -0,0 +1,275 @@
+use anyhow::Result;
+use std::{
+    io,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    frame::{Deframer, FrameFormat},
+    port::get_port_config,
+    stats::Stats,
+    test::test_config::TestConfig,
+    transport::Transport,
+};
+
+/// Breaks sent per test, each between two frames
+pub const BREAK_CYCLES: u64 = 3;
+/// Break length when TEST BEGIN doesn't say
+pub const DEFAULT_BREAK_MS: u64 = 100;
+/// Quiet time after releasing the break before the next frame
+const RECOVERY: Duration = Duration::from_millis(10);
+
+/// Hold a break between a frame before and a frame after it, `BREAK_CYCLES`
+/// times. Frame `2n` precedes break `n`, frame `2n + 1` follows it.
+pub fn run_break_tx(
+    port: &mut dyn Transport,
+    TestConfig {
+        payload,
+        break_ms,
+        format,
+        pattern,
+        ..
+    }: TestConfig,
+) -> Result<Stats> {
+    let port_config = get_port_config();
+    let format = FrameFormat::for_test(format, port_config.bits);
+    let mut stats = Stats::new(port_config.bits as u32);
+    let hold = Duration::from_millis(break_ms.unwrap_or(DEFAULT_BREAK_MS));
+    let mut breaks = 0;
+
+    // Give the receiver a moment to get from its ACK to its read loop
+    sleep(Duration::from_millis(100));
+
+    let start = Instant::now();
+    for cycle in 0..BREAK_CYCLES {
+        let before = format.encode(2 * cycle, &pattern.fill(2 * cycle, payload));
+        port.write_all(&before)?;
+        port.flush()?;
+        stats.add_bytes(before.len());
+        stats.inc_ok();
+
+        if let Err(e) = port.set_break() {
+            eprintln!("[test] break: cannot set break: {e:#}");
+            break;
+        }
+        sleep(hold);
+        port.clear_break()?;
+        breaks += 1;
+        sleep(RECOVERY);
+
+        let after = format.encode(2 * cycle + 1, &pattern.fill(2 * cycle + 1, payload));
+        port.write_all(&after)?;
+        port.flush()?;
+        stats.add_bytes(after.len());
+        stats.inc_ok();
+    }
+    stats.duration_micros = start.elapsed().as_micros() as u64;
+    stats.breaks = Some(breaks);
+
+    Ok(stats)
+}
+
+/// Receive the frames around each break and count the breaks marked in the
+/// stream (PARMRK). Driver `brk` counters are added by the runner.
+pub fn run_break_rx(
+    port: &mut dyn Transport,
+    TestConfig {
+        break_ms, format, ..
+    }: TestConfig,
+) -> Result<Stats> {
+    let port_config = get_port_config();
+    let format = FrameFormat::for_test(format, port_config.bits);
+    let mut stats = Stats::new(port_config.bits as u32);
+    let hold = Duration::from_millis(break_ms.unwrap_or(DEFAULT_BREAK_MS));
+
+    let parmrk = match port.set_parmrk(true) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("[test] break: no PARMRK ({e:#}), relying on driver counters");
+            false
+        }
+    };
+
+    let start = Instant::now();
+    let deadline = start + (hold + Duration::from_millis(500)) * BREAK_CYCLES as u32;
+    let mut marks = ParmrkDecoder::default();
+    // PARMRK has to come off again even if reading failed
+    let read = read_break_frames(port, format, deadline, &mut marks, &mut stats);
+    stats.duration_micros = start.elapsed().as_micros() as u64;
+    let restored = if parmrk {
+        port.set_parmrk(false)
+    } else {
+        Ok(())
+    };
+    read?;
+    restored?;
+    if parmrk {
+        stats.breaks = Some(marks.breaks);
+    }
+
+    Ok(stats)
+}
+
+/// Read frames until the last one or `deadline`, stripping PARMRK marks into `marks`
+fn read_break_frames(
+    port: &mut dyn Transport,
+    format: FrameFormat,
+    deadline: Instant,
+    marks: &mut ParmrkDecoder,
+    stats: &mut Stats,
+) -> Result<()> {
+    let last_seq = 2 * BREAK_CYCLES - 1;
+    let mut deframer = Deframer::new(format);
+    let mut buf = [0u8; 256];
+    let mut expect = 0;
+    'read: while Instant::now() < deadline {
+        let n = match port.read(&mut buf) {
+            Ok(n) => n,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let payload: Vec<u8> = buf[..n].iter().filter_map(|&b| marks.push(b)).collect();
+        deframer.push(&payload);
+        while let Some(chunk) = deframer.next_frame() {
+            stats.add_bytes(chunk.len());
+            match format.decode(&chunk) {
+                Ok(f) => {
+                    stats.inc_ok();
+                    if f.seq > expect {
+                        stats.add_lost(f.seq - expect);
+                    }
+                    expect = f.seq + 1;
+                    if f.seq >= last_seq {
+                        break 'read;
+                    }
+                }
+                Err(_) => {
+                    deframer.reject(chunk);
+                    stats.inc_bad();
+                }
+            }
+        }
+    }
+    if expect <= last_seq {
+        stats.add_lost(last_seq + 1 - expect);
+    }
+    Ok(())
+}
+
+/// Strips PARMRK marks from a byte stream, counting the breaks among them.
+#[derive(Debug, Default)]
+pub struct ParmrkDecoder {
+    state: u8, // bytes of an FF 00 <c> mark seen so far
+    pub breaks: u64,
+    pub errors: u64,
+}
+
+impl ParmrkDecoder {
+    /// Feed one byte; returns it if it is payload rather than part of a mark.
+    pub fn push(&mut self, b: u8) -> Option<u8> {
+        match (self.state, b) {
+            (0, 0xFF) => self.state = 1,
+            (0, _) => return Some(b),
+            (1, 0xFF) => {
+                self.state = 0;
+                return Some(0xFF);
+            }
+            (1, 0x00) => self.state = 2,
+            // Not a valid mark: pass it through rather than lose data
+            (1, _) => {
+                self.state = 0;
+                return Some(b);
+            }
+            (_, 0x00) => {
+                self.state = 0;
+                self.breaks += 1;
+            }
+            (_, _) => {
+                self.state = 0;
+                self.errors += 1;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auto::dataplane::TestOutcome,
+        pattern::Pattern,
+        proto::command::{Direction, FrameEncoding, TestName},
+        stats::DEFAULT_CONFIDENCE,
+        transport::loopback::{self, LinkModel},
+    };
+    use std::thread;
+
+    fn conf(dir: Direction) -> TestConfig {
+        TestConfig {
+            name: TestName::Break,
+            frames: None,
+            duration_ms: None,
+            payload: 8,
+            dir,
+            break_ms: Some(5),
+            format: FrameEncoding::Text,
+            pattern: Pattern::Counter,
+            confidence: DEFAULT_CONFIDENCE,
+            target_ber: None,
+        }
+    }
+
+    #[test]
+    fn parmrk_marks_are_stripped() {
+        let mut d = ParmrkDecoder::default();
+        let input = [b'A', 0xFF, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0x00, b'x', b'B'];
+        let out: Vec<u8> = input.iter().filter_map(|&b| d.push(b)).collect();
+        assert_eq!(out, [b'A', 0xFF, b'B']);
+        assert_eq!(d.breaks, 1);
+        assert_eq!(d.errors, 1);
+    }
+
+    #[test]
+    fn break_is_detected_and_link_recovers() {
+        let (mut a, mut b) = loopback::pair(LinkModel::default());
+        let before = b.error_counters().unwrap().unwrap();
+        let rx = thread::spawn(move || {
+            let mut stats = run_break_rx(&mut b, conf(Direction::Rx)).unwrap();
+            stats.driver = Some(b.error_counters().unwrap().unwrap().since(&before));
+            stats
+        });
+        let tx = run_break_tx(&mut a, conf(Direction::Tx)).unwrap();
+        let rx = rx.join().unwrap();
+        assert_eq!(rx.breaks, Some(BREAK_CYCLES));
+        let outcome = TestOutcome::from_break_stats(tx, rx);
+        assert!(outcome.pass, "{:?}", outcome);
+        assert_eq!(outcome.rx_frames, 2 * BREAK_CYCLES);
+    }
+
+    #[test]
+    fn binary_frames_survive_parmrk_doubling_0xff() {
+        let (mut a, mut b) = loopback::pair(LinkModel::default());
+        // An all-ones payload arrives as FF FF pairs while PARMRK is on
+        let conf = |dir| TestConfig {
+            format: FrameEncoding::Binary,
+            pattern: Pattern::Ones,
+            ..conf(dir)
+        };
+        let rx = thread::spawn(move || run_break_rx(&mut b, conf(Direction::Rx)).unwrap());
+        let tx = run_break_tx(&mut a, conf(Direction::Tx)).unwrap();
+        let rx = rx.join().unwrap();
+        assert_eq!(rx.breaks, Some(BREAK_CYCLES));
+        let outcome = TestOutcome::from_break_stats(tx, rx);
+        assert!(outcome.pass, "{:?}", outcome);
+        assert_eq!(outcome.rx_frames, 2 * BREAK_CYCLES);
+    }
+}
@@ -1,4 +1,7 @@
-use crate::proto::command::{Direction, TestName};
+use crate::{
+    pattern::Pattern,
+    proto::command::{Direction, FrameEncoding, TestName},
+};
 
 #[derive(Debug, Clone)]
 pub struct TestConfig {
@@ -7,4 +10,9 @@ pub struct TestConfig {
     pub duration_ms: Option<u64>,
     pub payload: usize, // bytes of payload per frame
     pub dir: Direction,
+    pub break_ms: Option<u64>, // how long the break test holds the line low
+    pub format: FrameEncoding, // frame encoding on the data plane
+    pub pattern: Pattern,      // what fills each frame's payload
+    pub confidence: f64,       // level the BER upper bound is quoted at
+    pub target_ber: Option<f64>, // fail unless the bound reaches this
 }
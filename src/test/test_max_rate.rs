@@ -1,23 +1,25 @@
 use anyhow::Result;
-use std::io::{BufRead, BufReader};
 
 use crate::{
     cli::Pacing,
-    frame::{build_frame, parse_frame},
+    frame::{Deframer, FrameFormat},
     port::get_port_config,
-    stats::Stats,
+    stats::{Stats, bit_errors},
     test::test_config::TestConfig,
+    transport::Transport,
 };
 
 const MAX_RATE: f64 = 0.999; // target 99.9% utilization
 const MAX_TEST_DURATION_MS: u64 = 20_000;
 
 pub fn run_max_rate_tx(
-    port: &mut dyn serialport::SerialPort,
+    port: &mut dyn Transport,
     TestConfig {
         duration_ms: input_duration_ms,
         frames,
         payload,
+        format,
+        pattern,
         ..
     }: TestConfig,
 ) -> Result<Stats> {
@@ -28,7 +30,7 @@ pub fn run_max_rate_tx(
     let bits_per_byte = port_config.bits_per_byte();
     let mut seq: u64 = 0;
     let pacing = Pacing::Auto { util: MAX_RATE };
-    let mut out = Vec::with_capacity(payload * 2 + 2);
+    let format = FrameFormat::for_test(format, port_config.bits);
 
     loop {
         if start.elapsed().as_millis() as u64 >= duration_ms {
@@ -39,10 +41,7 @@ pub fn run_max_rate_tx(
         {
             break;
         }
-        out.clear();
-        let line = build_frame(seq, payload);
-        out.extend_from_slice(line.as_bytes());
-        out.extend_from_slice(b"\r\n");
+        let out = format.encode(seq, &pattern.fill(seq, payload));
         port.write_all(&out)?;
 
         // Update stats
@@ -60,20 +59,30 @@ pub fn run_max_rate_tx(
 }
 
 pub fn run_max_rate_rx(
-    port: &mut dyn serialport::SerialPort,
+    port: &mut dyn Transport,
     TestConfig {
         duration_ms: input_duration_ms,
         frames,
+        payload,
+        format,
+        pattern,
         ..
     }: TestConfig,
 ) -> Result<Stats> {
     let start = std::time::Instant::now();
-    let mut reader = BufReader::new(port.try_clone()?); // Clone it for independent read/write handles
-    let mut line = String::new();
+    let mut reader = port.try_clone()?; // Clone it for independent read/write handles
 
-    let mut stats = crate::stats::Stats::new(get_port_config().bits as u32);
+    let port_config = get_port_config();
+    let format = FrameFormat::for_test(format, port_config.bits);
+    let mut deframer = Deframer::new(format);
+    let mut stats = crate::stats::Stats::new(port_config.bits as u32);
     let duration_ms = input_duration_ms.unwrap_or(MAX_TEST_DURATION_MS);
     let mut expect: Option<u64> = None;
+    let mut first = true;
+    // Bad frames since the last good one, whose bits are already counted
+    let mut charged: u64 = 0;
+    // Frames that never arrived: every bit of their payload counts as flipped
+    let mut missing: u64 = 0;
 
     loop {
         if start.elapsed().as_millis() as u64 >= duration_ms {
@@ -84,32 +93,336 @@ pub fn run_max_rate_rx(
         {
             break;
         }
-        line.clear();
 
-        let line_result = reader.read_line(&mut line);
-        let n = line_result.unwrap_or(0); // newline-terminated; timeout is set by builder
-        if n == 0 {
+        // timeout is set by builder
+        let Some(chunk) = deframer.read_frame(&mut *reader)? else {
             continue;
-        } // timeout
-        stats.add_bytes(n);
+        };
+        let decoded = format.decode(&chunk);
+        // Coming in mid-frame: drop its tail up to the delimiter and resync
+        if std::mem::take(&mut first) && decoded.is_err() && !format.has_frame_start(&chunk) {
+            stats.discarded += deframer.reject(chunk) as u64;
+            continue;
+        }
 
-        match parse_frame(line.trim_end()) {
+        match decoded {
             Ok(f) => {
-                stats.inc_ok();
+                stats.add_bytes(chunk.len());
+                // An 8-bit sum can let a corrupted payload through
+                let errors = bit_errors(&pattern.fill(f.seq, payload), &f.payload);
+                stats.add_bits(8 * payload.max(f.payload.len()) as u64, errors);
+                if errors == 0 {
+                    stats.inc_ok();
+                } else {
+                    stats.inc_bad();
+                }
                 if let Some(e) = expect
                     && f.seq != e
                 {
                     let lost = if f.seq > e { f.seq - e } else { 1 };
                     stats.add_lost(lost);
+                    missing += lost.saturating_sub(charged);
                 }
+                charged = 0;
                 expect = Some(f.seq.wrapping_add(1));
             }
             Err(_) => {
                 stats.inc_bad();
+                // Its seq is as suspect as the rest: take whichever of it
+                // and the expected seq the payload matches more closely
+                if let Ok(f) = format.decode_unchecked(&chunk) {
+                    let errors = [Some(f.seq), expect]
+                        .into_iter()
+                        .flatten()
+                        .map(|seq| bit_errors(&pattern.fill(seq, payload), &f.payload))
+                        .min()
+                        .unwrap_or_default();
+                    stats.add_bits(8 * payload.max(f.payload.len()) as u64, errors);
+                    charged += 1;
+                }
+                stats.add_bytes(deframer.reject(chunk));
             }
         }
     }
+    let lost_bits = missing * 8 * payload as u64;
+    stats.add_bits(lost_bits, lost_bits);
+    stats.discarded += deframer.discarded();
     stats.duration_micros = start.elapsed().as_micros() as u64;
 
     Ok(stats)
 }
+
+/// Charge `rx` with every payload bit of the frames `tx` sent after the last
+/// one RX accounted for. Only a TX that got all its frames out is charged:
+/// one cut short by the duration limit may still have its last frames on
+/// the wire when the RX stops.
+pub fn add_unreceived_tail(tx: &Stats, rx: &mut Stats, conf: &TestConfig) {
+    if conf.frames.is_some_and(|frames| tx.total >= frames) {
+        let lost_bits = tx.total.saturating_sub(rx.total) * 8 * conf.payload as u64;
+        rx.add_bits(lost_bits, lost_bits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auto::dataplane::TestOutcome,
+        cli::PortConfig,
+        pattern::Pattern,
+        port::DEFAULT_CONFIG,
+        proto::command::{Direction, FrameEncoding, Parity, TestName},
+        stats::{DEFAULT_CONFIDENCE, ERR_OVERRUN},
+        transport::{
+            Transport,
+            loopback::{self, LinkModel, LoopbackTransport},
+        },
+    };
+    use std::{io::Write, time::Duration};
+
+    const FRAMES: u64 = 20;
+
+    fn conf() -> TestConfig {
+        TestConfig {
+            name: TestName::MaxRate,
+            frames: Some(FRAMES),
+            duration_ms: Some(500),
+            payload: 16,
+            dir: Direction::Tx,
+            break_ms: None,
+            format: FrameEncoding::Text,
+            pattern: Pattern::Counter,
+            confidence: DEFAULT_CONFIDENCE,
+            target_ber: None,
+        }
+    }
+
+    /// Stream frames A -> B over the simulated link, returning (tx, rx) stats.
+    fn run_link(a: &mut LoopbackTransport, b: &mut LoopbackTransport) -> (Stats, Stats) {
+        let tx = run_max_rate_tx(a, conf()).unwrap();
+        let rx = run_max_rate_rx(b, conf()).unwrap();
+        (tx, rx)
+    }
+
+    #[test]
+    fn clean_link_passes() {
+        let (mut a, mut b) = loopback::pair(LinkModel::default());
+        let (tx, rx) = run_link(&mut a, &mut b);
+        let outcome = TestOutcome::from_test_stats(tx, rx);
+        assert!(outcome.pass, "{:?}", outcome);
+        assert_eq!(outcome.rx_frames, FRAMES);
+        assert_eq!(outcome.bad_crc, 0);
+        assert_eq!(outcome.seq_gaps, 0);
+        assert_eq!(outcome.bits_compared, FRAMES * 16 * 8);
+        assert_eq!(outcome.ber, Some(0.0));
+    }
+
+    #[test]
+    fn throughput_matches_line_rate() {
+        let (mut a, mut b) = loopback::pair(LinkModel::default());
+        let (mut tx, rx) = run_link(&mut a, &mut b);
+        assert_eq!(tx.bytes, rx.bytes);
+        // Measure against virtual wire time instead of the wall clock
+        tx.duration_micros = a.wire_time().as_micros() as u64;
+        let outcome = TestOutcome::from_test_stats(tx, rx);
+        // 8 data bits out of every 10 line bits at 115200 baud
+        assert!(
+            outcome.rate_bps.abs_diff(92_160) <= 10,
+            "{}",
+            outcome.rate_bps
+        );
+    }
+
+    #[test]
+    fn clock_skew_reports_crc_errors() {
+        // B samples 5.3% fast: with 8O1 its stop bit lands on the parity bit,
+        // so every character with parity bit 0 is a framing error (read as NUL).
+        let model = LinkModel {
+            skew_ppm: 53_000.0,
+            ..LinkModel::default()
+        };
+        let (mut a, mut b) = loopback::pair(model);
+        let odd = PortConfig {
+            parity: Parity::Odd,
+            ..DEFAULT_CONFIG
+        };
+        a.retune(&odd).unwrap();
+        b.retune(&odd).unwrap();
+        let (tx, rx) = run_link(&mut a, &mut b);
+        assert!(b.counters().frame > 0);
+        let outcome = TestOutcome::from_test_stats(tx, rx);
+        assert!(!outcome.pass);
+        assert!(outcome.bad_crc > 0);
+        assert_eq!(outcome.reason.as_deref(), Some("crc errors"));
+    }
+
+    #[test]
+    fn fifo_overrun_reports_seq_gaps() {
+        // ~16.1 characters per service interval into a 16 byte FIFO: an
+        // occasional byte is dropped, corrupting that frame only.
+        let model = LinkModel {
+            fifo_depth: 16,
+            service_interval: Duration::from_micros(1_400),
+            ..LinkModel::default()
+        };
+        let (mut a, mut b) = loopback::pair(model);
+        let before = b.error_counters().unwrap().unwrap();
+        let (tx, mut rx) = run_link(&mut a, &mut b);
+        rx.driver = Some(b.error_counters().unwrap().unwrap().since(&before));
+        let outcome = TestOutcome::from_test_stats(tx, rx);
+        assert!(!outcome.pass);
+        assert!(outcome.rx_frames > 0);
+        assert!(outcome.seq_gaps > 0);
+        assert!(outcome.overruns > 0);
+        assert_eq!(outcome.errors, ERR_OVERRUN);
+    }
+
+    #[test]
+    fn binary_frames_resync_after_line_noise() {
+        let (mut a, mut b) = loopback::pair(LinkModel::default());
+        let conf = TestConfig {
+            format: FrameEncoding::Binary,
+            ..conf()
+        };
+        // Junk ahead of the first frame, including a stray SYNC byte
+        a.write_all(&[0x5A, 0xA5, 0x00, 0xFF, 0x0D, 0x0A]).unwrap();
+        let tx = run_max_rate_tx(&mut a, conf.clone()).unwrap();
+        let rx = run_max_rate_rx(&mut b, conf).unwrap();
+        assert_eq!(tx.bytes, rx.bytes);
+        let outcome = TestOutcome::from_test_stats(tx, rx);
+        assert!(outcome.pass, "{:?}", outcome);
+        assert_eq!(outcome.rx_frames, FRAMES);
+    }
+
+    #[test]
+    fn cobs_rx_resyncs_on_the_delimiter() {
+        let (mut a, mut b) = loopback::pair(LinkModel::default());
+        let conf = TestConfig {
+            format: FrameEncoding::Cobs,
+            ..conf()
+        };
+        // The tail of a frame already in flight when RX started
+        let tail = &FrameFormat::Cobs.encode(99, &[0x11; 16])[10..];
+        a.write_all(tail).unwrap();
+        let tx = run_max_rate_tx(&mut a, conf.clone()).unwrap();
+        let rx = run_max_rate_rx(&mut b, conf).unwrap();
+        assert_eq!(rx.discarded, tail.len() as u64);
+        let outcome = TestOutcome::from_test_stats(tx, rx);
+        assert!(outcome.pass, "{:?}", outcome);
+        assert_eq!(outcome.rx_frames, FRAMES);
+        assert_eq!(outcome.discarded, tail.len() as u64);
+    }
+
+    #[test]
+    fn a_corrupted_first_frame_is_bad_not_discarded() {
+        let (mut a, mut b) = loopback::pair(LinkModel::default());
+        let mut first = FrameFormat::Hex.encode(0, &Pattern::Counter.fill(0, 16));
+        let at = first.windows(4).position(|w| w == b"PAY=").unwrap() + 5;
+        first[at] ^= 0x01;
+        a.write_all(&first).unwrap();
+        for seq in 1..FRAMES {
+            a.write_all(&FrameFormat::Hex.encode(seq, &Pattern::Counter.fill(seq, 16)))
+                .unwrap();
+        }
+        let rx = run_max_rate_rx(&mut b, conf()).unwrap();
+        assert_eq!(rx.bad, 1);
+        assert_eq!(rx.ok, FRAMES - 1);
+        assert_eq!(rx.discarded, 0);
+    }
+
+    #[test]
+    fn flipped_bits_are_counted_against_the_pattern() {
+        let (mut a, mut b) = loopback::pair(LinkModel::default());
+        let conf = TestConfig {
+            format: FrameEncoding::Binary,
+            pattern: Pattern::Prbs7,
+            // A bad frame also leaves a seq gap, so run until the link idles
+            frames: None,
+            duration_ms: Some(200),
+            ..conf()
+        };
+        for seq in 0..FRAMES {
+            let mut out = FrameFormat::Binary.encode(seq, &Pattern::Prbs7.fill(seq, 16));
+            // Three bits in every fourth frame, one of them in the seq
+            if seq % 4 == 1 {
+                out[7] ^= 0x02;
+                out[10] ^= 0x80;
+                out[20] ^= 0x01;
+            }
+            a.write_all(&out).unwrap();
+        }
+        let rx = run_max_rate_rx(&mut b, conf).unwrap();
+        assert_eq!(rx.bad, FRAMES / 4);
+        assert_eq!(rx.bits_compared, FRAMES * 16 * 8);
+        // The corrupted seq is recognised as such and only its two payload
+        // bits count
+        assert_eq!(rx.bit_errors, 2 * FRAMES / 4);
+        assert_eq!(rx.ber(), Some(2.0 * 5.0 / (FRAMES * 128) as f64));
+    }
+
+    #[test]
+    fn lost_frames_count_as_errored_bits() {
+        let (mut a, mut b) = loopback::pair(LinkModel::default());
+        // Two frames dropped mid-run and the last one never sent
+        for seq in (0..FRAMES - 1).filter(|s| !(5..7).contains(s)) {
+            a.write_all(&FrameFormat::Hex.encode(seq, &Pattern::Counter.fill(seq, 16)))
+                .unwrap();
+        }
+        let mut rx = run_max_rate_rx(&mut b, conf()).unwrap();
+        assert_eq!((rx.ok, rx.lost), (FRAMES - 3, 2));
+        assert_eq!(rx.bit_errors, 2 * 16 * 8);
+        // The TX says it sent them all, so the last one is lost too
+        let mut tx = Stats::new(8);
+        (0..FRAMES).for_each(|_| tx.inc_ok());
+        add_unreceived_tail(&tx, &mut rx, &conf());
+        assert_eq!(rx.bits_compared, FRAMES * 16 * 8);
+        assert_eq!(rx.bit_errors, 3 * 16 * 8);
+    }
+
+    #[test]
+    fn a_tx_cut_short_by_the_duration_is_not_charged_a_tail() {
+        let (mut a, mut b) = loopback::pair(LinkModel::default());
+        for seq in 0..FRAMES - 2 {
+            a.write_all(&FrameFormat::Hex.encode(seq, &Pattern::Counter.fill(seq, 16)))
+                .unwrap();
+        }
+        let mut rx = run_max_rate_rx(&mut b, conf()).unwrap();
+        // Ran out of time one frame short; the last one it sent is in flight
+        let mut tx = Stats::new(8);
+        (0..FRAMES - 1).for_each(|_| tx.inc_ok());
+        add_unreceived_tail(&tx, &mut rx, &conf());
+        assert_eq!(rx.bit_errors, 0);
+        assert_eq!(rx.ber(), Some(0.0));
+    }
+
+    #[test]
+    fn ber_target_needs_enough_clean_bits() {
+        let (mut a, mut b) = loopback::pair(LinkModel::default());
+        let (tx, rx) = run_link(&mut a, &mut b);
+        let outcome = TestOutcome::from_test_stats(tx, rx);
+        // 2560 clean bits: good for 1e-2 at 95%, not for 1e-3
+        let mut met = outcome.clone();
+        met.check_ber(0.95, Some(1e-2));
+        assert!(met.pass, "{:?}", met);
+        assert!(met.ber_bound.unwrap() < 1.2e-3);
+        let mut missed = outcome;
+        missed.check_ber(0.95, Some(1e-3));
+        assert!(!missed.pass);
+        assert_eq!(missed.reason.as_deref(), Some("ber target not reached"));
+    }
+
+    #[test]
+    fn prbs_payload_over_cobs_frames() {
+        let (mut a, mut b) = loopback::pair(LinkModel::default());
+        let conf = TestConfig {
+            format: FrameEncoding::Cobs,
+            pattern: Pattern::Prbs15,
+            ..conf()
+        };
+        let tx = run_max_rate_tx(&mut a, conf.clone()).unwrap();
+        let rx = run_max_rate_rx(&mut b, conf).unwrap();
+        let outcome = TestOutcome::from_test_stats(tx, rx);
+        assert!(outcome.pass, "{:?}", outcome);
+        assert_eq!(outcome.rx_frames, FRAMES);
+    }
+}
@@ -0,0 +1,175 @@
+use anyhow::Result;
+use std::{
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    port::{get_port_config, wait_for_command, write_line},
+    proto::{
+        command::CtrlCommand,
+        parser::{format_command, parse_command},
+    },
+    stats::Stats,
+    transport::{LineReader, ModemLines, Transport},
+};
+
+/// (DTR, RTS) levels driven in turn; every output goes both ways at least once
+pub const LINE_STEPS: [(bool, bool); 4] =
+    [(false, false), (true, false), (false, true), (true, true)];
+/// Time for the levels to settle before the peer samples them
+const SETTLE: Duration = Duration::from_millis(20);
+/// How long the driving side waits for a `LINES STATE` reply
+const REPLY_TIMEOUT: Duration = Duration::from_millis(2_000);
+/// How long the sampling side waits for the next `LINES SET`
+const STEP_TIMEOUT: Duration = Duration::from_millis(5_000);
+
+/// Drive DTR/RTS of `data` through `LINE_STEPS`, ask the peer over `ctrl`
+/// what it sees, and check that its DSR follows our DTR and its
+/// CTS follows our RTS (null-modem wiring). CD and RI are only reported,
+/// since cables differ in where (if anywhere) they are wired.
+pub fn run_modem_lines_tx(
+    ctrl: &mut LineReader,
+    data: &mut dyn Transport,
+    my_id: &str,
+) -> Result<Stats> {
+    let mut stats = Stats::new(get_port_config().bits as u32);
+    let start = Instant::now();
+
+    for (i, (dtr, rts)) in LINE_STEPS.into_iter().enumerate() {
+        // e.g. PTYs and sockets have no modem lines; let the peer time out
+        if let Err(e) = data.write_lines(dtr, rts) {
+            eprintln!("[test] lines: cannot drive DTR/RTS: {e:#}");
+            stats.add_lost((LINE_STEPS.len() - i) as u64);
+            stats.duration_micros = start.elapsed().as_micros() as u64;
+            return Ok(stats);
+        }
+        sleep(SETTLE);
+        let set = CtrlCommand::LinesSet {
+            id: my_id.to_string(),
+            dtr,
+            rts,
+        };
+        write_line(ctrl, &format_command(&set))?;
+        let reply = wait_for_command(ctrl, Some(REPLY_TIMEOUT), |line: &str| {
+            if let Ok(CtrlCommand::LinesState {
+                cts, dsr, cd, ri, ..
+            }) = parse_command(line)
+            {
+                return Some(ModemLines { cts, dsr, cd, ri });
+            }
+            None
+        });
+        let Ok(seen) = reply else {
+            eprintln!("[test] lines dtr={} rts={}: no reply", dtr as u8, rts as u8);
+            stats.add_lost(1);
+            continue;
+        };
+
+        let mut wrong = Vec::new();
+        if seen.dsr != dtr {
+            wrong.push("dsr");
+        }
+        if seen.cts != rts {
+            wrong.push("cts");
+        }
+        eprintln!(
+            "[test] lines dtr={} rts={} -> peer cts={} dsr={} cd={} ri={} {}",
+            dtr as u8,
+            rts as u8,
+            seen.cts as u8,
+            seen.dsr as u8,
+            seen.cd as u8,
+            seen.ri as u8,
+            if wrong.is_empty() {
+                "ok".to_string()
+            } else {
+                format!("MISMATCH({})", wrong.join(","))
+            }
+        );
+        if wrong.is_empty() {
+            stats.inc_ok();
+        } else {
+            stats.inc_bad();
+        }
+    }
+
+    // Leave the outputs asserted, as they are after open()
+    data.write_lines(true, true)?;
+    stats.duration_micros = start.elapsed().as_micros() as u64;
+
+    Ok(stats)
+}
+
+/// Answer each `LINES SET` from the peer with the inputs we sample on `data`.
+pub fn run_modem_lines_rx(ctrl: &mut LineReader, data: &mut dyn Transport) -> Result<Stats> {
+    let mut stats = Stats::new(get_port_config().bits as u32);
+    let start = Instant::now();
+
+    for _ in LINE_STEPS {
+        let set = wait_for_command(ctrl, Some(STEP_TIMEOUT), |line: &str| {
+            match parse_command(line) {
+                Ok(cmd @ CtrlCommand::LinesSet { .. }) => Some(cmd),
+                _ => None,
+            }
+        });
+        let Ok(CtrlCommand::LinesSet { id, .. }) = set else {
+            break;
+        };
+        let ModemLines { cts, dsr, cd, ri } = match data.read_lines() {
+            Ok(lines) => lines,
+            Err(e) => {
+                eprintln!("[test] lines: cannot read CTS/DSR/CD/RI: {e:#}");
+                break;
+            }
+        };
+        let state = CtrlCommand::LinesState {
+            id,
+            cts,
+            dsr,
+            cd,
+            ri,
+        };
+        write_line(ctrl, &format_command(&state))?;
+        stats.inc_ok();
+    }
+    stats.duration_micros = start.elapsed().as_micros() as u64;
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::loopback::{self, LinkModel};
+    use std::thread;
+
+    fn run_pair(model: LinkModel) -> (Stats, Stats) {
+        let (a, b) = loopback::pair(model);
+        let (mut a_data, mut b_data) = (a.try_clone().unwrap(), b.try_clone().unwrap());
+        let (mut a, mut b) = (LineReader::new(Box::new(a)), LineReader::new(Box::new(b)));
+        let sampler = thread::spawn(move || run_modem_lines_rx(&mut b, &mut *b_data).unwrap());
+        let driver = run_modem_lines_tx(&mut a, &mut *a_data, "m1").unwrap();
+        (driver, sampler.join().unwrap())
+    }
+
+    #[test]
+    fn null_modem_lines_follow() {
+        let (driver, sampler) = run_pair(LinkModel::default());
+        assert_eq!(driver.ok, LINE_STEPS.len() as u64);
+        assert_eq!(driver.bad + driver.lost, 0);
+        assert_eq!(sampler.ok, LINE_STEPS.len() as u64);
+    }
+
+    #[test]
+    fn dead_cts_pin_is_caught() {
+        let model = LinkModel {
+            dead_cts: true,
+            ..LinkModel::default()
+        };
+        let (driver, _) = run_pair(model);
+        // Only the steps with RTS asserted can tell a dead CTS from a live one
+        assert_eq!(driver.bad, 2);
+        assert_eq!(driver.ok, 2);
+    }
+}
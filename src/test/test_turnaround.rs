@@ -0,0 +1,260 @@
+use anyhow::Result;
+use std::{
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    frame::{Deframer, FrameFormat},
+    port::get_port_config,
+    stats::Stats,
+    test::test_config::TestConfig,
+    transport::Transport,
+};
+
+/// Reply gaps the responder steps through, longest first
+pub const TURNAROUND_GAPS_US: [u64; 8] = [2_000, 1_000, 500, 200, 100, 50, 20, 0];
+/// Pings sent when the test is only bounded by duration
+const DEFAULT_PINGS: u64 = 200;
+/// Added to a ping's seq for its reply, so a half-duplex echo of our own
+/// frame is never mistaken for the peer's answer
+const PONG_SEQ_BASE: u64 = 1 << 20;
+/// Scheduling and driver latency allowed on top of the wire time
+const REPLY_SLACK: Duration = Duration::from_millis(50);
+/// Responder gives up after this long without a ping
+const IDLE_TIMEOUT: Duration = Duration::from_millis(1_000);
+
+fn total_pings(frames: Option<u64>) -> u64 {
+    frames.unwrap_or(DEFAULT_PINGS).max(1)
+}
+
+/// Gap the responder waits before answering ping `seq`
+pub fn gap_for_seq(seq: u64, total: u64) -> Duration {
+    let per_step = total.div_ceil(TURNAROUND_GAPS_US.len() as u64).max(1);
+    let step = ((seq / per_step) as usize).min(TURNAROUND_GAPS_US.len() - 1);
+    Duration::from_micros(TURNAROUND_GAPS_US[step])
+}
+
+/// Half-duplex ping side: send a frame, wait for the peer's answer, repeat
+/// with the peer answering sooner and sooner after our last stop bit.
+///
+/// A step is clean when every reply arrived intact (no collision, no lost
+/// leading bytes). Each reply is timed from the ping's last byte leaving
+/// (`flush` returning) to its own first byte arriving; the shortest of those
+/// in the steps down to the first unclean one ends up in
+/// `stats.turnaround_us`.
+pub fn run_turnaround_tx(
+    port: &mut dyn Transport,
+    TestConfig {
+        frames,
+        payload,
+        format,
+        pattern,
+        ..
+    }: TestConfig,
+) -> Result<Stats> {
+    let port_config = get_port_config();
+    let format = FrameFormat::for_test(format, port_config.bits);
+    let mut stats = Stats::new(port_config.bits as u32);
+    let total = total_pings(frames);
+    let mut reader = port.try_clone()?;
+    let mut deframer = Deframer::new(format);
+
+    let frame_len = format.encode(0, &pattern.fill(0, payload)).len();
+    let wire_time = Duration::from_secs_f64(
+        (2 * frame_len) as f64 * port_config.bits_per_byte() as f64 / port_config.baud as f64,
+    );
+
+    // Give the responder a moment to get from its ACK to its read loop
+    sleep(Duration::from_millis(100));
+
+    let start = Instant::now();
+    let mut step_clean = true;
+    let mut step_fastest: Option<Duration> = None;
+    let mut all_clean = true;
+    for seq in 0..total {
+        let gap = gap_for_seq(seq, total);
+        let out = format.encode(seq, &pattern.fill(seq, payload));
+        port.write_all(&out)?;
+        port.flush()?;
+        let sent = Instant::now();
+        stats.add_bytes(out.len());
+
+        let deadline = sent + wire_time + gap + REPLY_SLACK;
+        // When the first byte not yet accounted for by another frame came in
+        let mut first_byte: Option<Instant> = None;
+        let ok = loop {
+            if Instant::now() >= deadline {
+                stats.add_lost(1);
+                break false;
+            }
+            let chunk = deframer.read_frame(&mut *reader)?;
+            let now = Instant::now();
+            let Some(chunk) = chunk else {
+                if deframer.buffered() > 0 {
+                    first_byte.get_or_insert(now);
+                }
+                continue;
+            };
+            let arrived = *first_byte.get_or_insert(now);
+            match format.decode(&chunk) {
+                Ok(f) if f.seq == PONG_SEQ_BASE + seq => {
+                    stats.inc_ok();
+                    let delay = arrived - sent;
+                    step_fastest = Some(step_fastest.map_or(delay, |d| d.min(delay)));
+                    break true;
+                }
+                // Echo of our own ping on a half-duplex bus, or a late reply
+                // to an earlier ping: the reply starts after it
+                Ok(f) if f.seq < PONG_SEQ_BASE + seq => {
+                    first_byte = (deframer.buffered() > 0).then_some(now);
+                }
+                Ok(_) => {
+                    stats.inc_bad();
+                    break false;
+                }
+                Err(_) => {
+                    deframer.reject(chunk);
+                    stats.inc_bad();
+                    break false;
+                }
+            }
+        };
+        step_clean &= ok;
+
+        let last_of_step = seq + 1 == total || gap_for_seq(seq + 1, total) != gap;
+        if last_of_step {
+            eprintln!(
+                "[test] turnaround gap={}us {}",
+                gap.as_micros(),
+                if step_clean {
+                    "clean"
+                } else {
+                    "lost/corrupted replies"
+                }
+            );
+            // Only count steps down to the first one that lost replies
+            all_clean &= step_clean;
+            if all_clean && let Some(fastest) = step_fastest {
+                let us = fastest.as_micros() as u64;
+                stats.turnaround_us = Some(stats.turnaround_us.map_or(us, |t| t.min(us)));
+            }
+            step_clean = true;
+            step_fastest = None;
+        }
+    }
+    stats.duration_micros = start.elapsed().as_micros() as u64;
+
+    Ok(stats)
+}
+
+/// Half-duplex answer side: reply to every intact ping after the gap its
+/// seq calls for. Returns once the last ping was answered or the bus idles.
+pub fn run_turnaround_rx(
+    port: &mut dyn Transport,
+    TestConfig {
+        frames,
+        duration_ms,
+        payload,
+        format,
+        pattern,
+        ..
+    }: TestConfig,
+) -> Result<Stats> {
+    let port_config = get_port_config();
+    let format = FrameFormat::for_test(format, port_config.bits);
+    let mut stats = Stats::new(port_config.bits as u32);
+    let total = total_pings(frames);
+    let mut reader = port.try_clone()?;
+    let mut deframer = Deframer::new(format);
+
+    let start = Instant::now();
+    let first_ping_by = start + Duration::from_millis(duration_ms.unwrap_or(5_000).max(5_000));
+    let mut last_ping: Option<Instant> = None;
+    loop {
+        match last_ping {
+            Some(t) if t.elapsed() >= IDLE_TIMEOUT => break,
+            None if Instant::now() >= first_ping_by => break,
+            _ => {}
+        }
+        let Some(chunk) = deframer.read_frame(&mut *reader)? else {
+            continue;
+        };
+        stats.add_bytes(chunk.len());
+        match format.decode(&chunk) {
+            Ok(f) if f.seq < total => {
+                last_ping = Some(Instant::now());
+                sleep(gap_for_seq(f.seq, total));
+                let pong = PONG_SEQ_BASE + f.seq;
+                port.write_all(&format.encode(pong, &pattern.fill(pong, payload)))?;
+                port.flush()?;
+                stats.inc_ok();
+                if f.seq + 1 == total {
+                    break;
+                }
+            }
+            // Echo of our own reply on a half-duplex bus
+            Ok(_) => {}
+            Err(_) => {
+                deframer.reject(chunk);
+                stats.inc_bad();
+            }
+        }
+    }
+    stats.duration_micros = start.elapsed().as_micros() as u64;
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pattern::Pattern,
+        proto::command::{Direction, FrameEncoding, TestName},
+        stats::DEFAULT_CONFIDENCE,
+        transport::loopback::{self, LinkModel},
+    };
+    use std::thread;
+
+    fn conf(dir: Direction) -> TestConfig {
+        TestConfig {
+            name: TestName::Turnaround,
+            frames: Some(16),
+            duration_ms: None,
+            payload: 8,
+            dir,
+            break_ms: None,
+            format: FrameEncoding::Text,
+            pattern: Pattern::Counter,
+            confidence: DEFAULT_CONFIDENCE,
+            target_ber: None,
+        }
+    }
+
+    #[test]
+    fn gaps_step_down_evenly() {
+        assert_eq!(gap_for_seq(0, 16), Duration::from_micros(2_000));
+        assert_eq!(gap_for_seq(1, 16), Duration::from_micros(2_000));
+        assert_eq!(gap_for_seq(2, 16), Duration::from_micros(1_000));
+        assert_eq!(gap_for_seq(15, 16), Duration::ZERO);
+        // Fewer pings than steps: one each, the rest never reached
+        assert_eq!(gap_for_seq(2, 3), Duration::from_micros(500));
+        assert_eq!(gap_for_seq(99, 3), Duration::ZERO);
+    }
+
+    #[test]
+    fn full_duplex_link_answers_without_gap() {
+        let (mut a, mut b) = loopback::pair(LinkModel::default());
+        let responder = thread::spawn(move || run_turnaround_rx(&mut b, conf(Direction::Rx)));
+        let tx = run_turnaround_tx(&mut a, conf(Direction::Tx)).unwrap();
+        let rx = responder.join().unwrap().unwrap();
+        assert_eq!(tx.ok, 16);
+        assert_eq!(tx.bad + tx.lost, 0);
+        assert_eq!(rx.ok, 16);
+        // Measured rather than the responder's scheduled gap, so the fastest
+        // reply comes in well short of the first 2 ms step
+        let turnaround = tx.turnaround_us.unwrap();
+        assert!(turnaround < 2_000, "{}", turnaround);
+    }
+}
@@ -0,0 +1,240 @@
+//! Buffered line reader for the control channel.
+//!
+//! Reads the link in chunks and keeps whatever follows the last complete
+//! line for the next call, so a command that straddles a read timeout is not
+//! lost. Clones share that buffer: in band the data plane reads from a clone
+//! and so picks up frame bytes that arrived right behind a TEST BEGIN (ACK).
+
+use anyhow::Result;
+use std::{
+    io::{self, Read, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    cli::{PortConfig, Rs485Opts},
+    debug_eprintln,
+    stats::DriverCounters,
+    transport::{ModemLines, Transport},
+};
+
+/// Longest control line; anything longer is line noise and dropped, save
+/// the newest bytes still waiting for a line end
+pub const MAX_LINE_LEN: usize = 512;
+/// Bytes asked for per read
+const CHUNK: usize = 256;
+
+pub struct LineReader {
+    inner: Box<dyn Transport>,
+    pending: Arc<Mutex<Vec<u8>>>,
+}
+
+impl LineReader {
+    pub fn new(inner: Box<dyn Transport>) -> Self {
+        Self {
+            inner,
+            pending: Arc::default(),
+        }
+    }
+
+    /// Read until a full line (LF or CRLF terminated, terminator trimmed)
+    /// is buffered.
+    ///
+    /// - Ok(Some(line)) → a full line was read
+    /// - Ok(None)       → none yet (WouldBlock, TimedOut, Ok(0)); partial input is kept
+    /// - Err(e)         → unexpected I/O error
+    pub fn read_line(&mut self) -> Result<Option<String>> {
+        let mut chunk = [0u8; CHUNK];
+        loop {
+            if let Some(line) = self.take_line() {
+                return Ok(Some(line));
+            }
+            match self.inner.read(&mut chunk) {
+                // Some backends say "nothing right now" this way
+                Ok(0) => return Ok(None),
+                Ok(n) => self.pending.lock().unwrap().extend_from_slice(&chunk[..n]),
+                Err(e) => match e.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => return Ok(None),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(e.into()),
+                },
+            }
+        }
+    }
+
+    /// Bytes read but not yet returned as a line
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    fn take_line(&mut self) -> Option<String> {
+        let mut pending = self.pending.lock().unwrap();
+        loop {
+            let Some(end) = pending.iter().position(|&b| b == b'\n') else {
+                // Keep the tail: it may be the start of a real line
+                if let Some(excess) = pending.len().checked_sub(MAX_LINE_LEN + 1) {
+                    debug_eprintln!("[port] dropping {} bytes without a line end", excess + 1);
+                    pending.drain(..=excess);
+                }
+                return None;
+            };
+            let mut line: Vec<u8> = pending.drain(..=end).collect();
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if line.len() > MAX_LINE_LEN {
+                debug_eprintln!("[port] dropping {}-byte line", line.len());
+                continue;
+            }
+            return Some(String::from_utf8_lossy(&line).into_owned());
+        }
+    }
+}
+
+impl Transport for LineReader {
+    fn retune(&mut self, cfg: &PortConfig) -> Result<()> {
+        self.inner.retune(cfg)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.inner.set_timeout(timeout)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.pending.lock().unwrap().clear();
+        self.inner.clear()
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(Self {
+            inner: self.inner.try_clone()?,
+            pending: self.pending.clone(),
+        }))
+    }
+
+    fn applied_config(&self) -> Result<Option<PortConfig>> {
+        self.inner.applied_config()
+    }
+
+    fn error_counters(&self) -> Result<Option<DriverCounters>> {
+        self.inner.error_counters()
+    }
+
+    fn write_lines(&mut self, dtr: bool, rts: bool) -> Result<()> {
+        self.inner.write_lines(dtr, rts)
+    }
+
+    fn read_lines(&mut self) -> Result<ModemLines> {
+        self.inner.read_lines()
+    }
+
+    fn set_break(&mut self) -> Result<()> {
+        self.inner.set_break()
+    }
+
+    fn clear_break(&mut self) -> Result<()> {
+        self.inner.clear_break()
+    }
+
+    fn set_parmrk(&mut self, on: bool) -> Result<()> {
+        self.inner.set_parmrk(on)
+    }
+
+    fn set_rs485(&mut self, opts: &Rs485Opts) -> Result<()> {
+        self.inner.set_rs485(opts)
+    }
+}
+
+impl Read for LineReader {
+    /// Buffered bytes first, then the link
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if !pending.is_empty() {
+                let n = pending.len().min(buf.len());
+                buf[..n].copy_from_slice(&pending[..n]);
+                pending.drain(..n);
+                return Ok(n);
+            }
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl Write for LineReader {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::loopback::{self, LinkModel};
+
+    fn pair() -> (Box<dyn Transport>, LineReader) {
+        let (a, b) = loopback::pair(LinkModel::default());
+        (Box::new(a), LineReader::new(Box::new(b)))
+    }
+
+    #[test]
+    fn partial_line_survives_a_timeout() {
+        let (mut tx, mut rx) = pair();
+        tx.write_all(b"HELLO id=").unwrap();
+        assert_eq!(rx.read_line().unwrap(), None);
+        tx.write_all(b"m1\r\nACK id=a1\n").unwrap();
+        assert_eq!(rx.read_line().unwrap().as_deref(), Some("HELLO id=m1"));
+        // Lone LF ends a line too
+        assert_eq!(rx.read_line().unwrap().as_deref(), Some("ACK id=a1"));
+    }
+
+    #[test]
+    fn garbage_flood_is_dropped() {
+        let (mut tx, mut rx) = pair();
+        tx.write_all(&[b'x'; MAX_LINE_LEN + CHUNK]).unwrap();
+        while rx.read_line().unwrap().is_some() {}
+        assert!(rx.pending() <= MAX_LINE_LEN);
+        tx.write_all(b"\r\nTERMINATE id=m1\r\n").unwrap();
+        let mut lines = Vec::new();
+        while let Some(line) = rx.read_line().unwrap() {
+            lines.push(line);
+        }
+        // The tail of the flood comes out as one over-long line or not at all
+        assert_eq!(lines.last().map(String::as_str), Some("TERMINATE id=m1"));
+        assert!(lines.iter().all(|l| l.len() <= MAX_LINE_LEN));
+    }
+
+    #[test]
+    fn a_flood_keeps_its_newest_bytes() {
+        let (mut tx, mut rx) = pair();
+        let mut flood = vec![b'x'; 3 * CHUNK];
+        flood.extend_from_slice(b"TERMINATE id=m1");
+        tx.write_all(&flood).unwrap();
+        while rx.read_line().unwrap().is_some() {}
+        assert_eq!(rx.pending(), MAX_LINE_LEN);
+        tx.write_all(b"\n").unwrap();
+        let line = rx.read_line().unwrap().unwrap();
+        assert_eq!(line.len(), MAX_LINE_LEN);
+        assert!(line.ends_with("xTERMINATE id=m1"));
+    }
+
+    #[test]
+    fn bytes_after_a_line_go_to_the_data_plane() {
+        let (mut tx, mut rx) = pair();
+        let mut data = rx.try_clone().unwrap();
+        tx.write_all(b"TEST BEGIN ACK id=a1\r\n@@ SEQ=0").unwrap();
+        assert_eq!(
+            rx.read_line().unwrap().as_deref(),
+            Some("TEST BEGIN ACK id=a1")
+        );
+        let mut buf = [0u8; 32];
+        let n = data.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"@@ SEQ=0");
+    }
+}
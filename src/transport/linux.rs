@@ -0,0 +1,123 @@
+//! Linux-only tty ioctls the `serialport` crate does not expose.
+
+use std::{io, mem::MaybeUninit, os::fd::RawFd};
+
+use crate::stats::DriverCounters;
+
+fn tcgets2(fd: RawFd) -> io::Result<libc::termios2> {
+    let mut tio = MaybeUninit::<libc::termios2>::uninit();
+    if unsafe { libc::ioctl(fd, libc::TCGETS2, tio.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { tio.assume_init() })
+}
+
+fn tcsets2(fd: RawFd, tio: &libc::termios2) -> io::Result<()> {
+    if unsafe { libc::ioctl(fd, libc::TCSETS2, tio) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Set an arbitrary baud rate with `TCSETS2` + `BOTHER`.
+pub fn set_baud(fd: RawFd, baud: u32) -> io::Result<()> {
+    let mut tio = tcgets2(fd)?;
+    tio.c_cflag &= !(libc::CBAUD | libc::CIBAUD);
+    tio.c_cflag |= libc::BOTHER;
+    tio.c_ispeed = baud;
+    tio.c_ospeed = baud;
+    tcsets2(fd, &tio)
+}
+
+/// Baud rate the driver actually applied, read back with `TCGETS2`.
+pub fn get_baud(fd: RawFd) -> io::Result<u32> {
+    Ok(tcgets2(fd)?.c_ospeed)
+}
+
+/// Turn `PARMRK` on, so breaks are no longer ignored or turned into signals
+/// but read as `FF 00 00`. Returns the `c_iflag` it replaced, for
+/// [`set_iflag`] to put back.
+pub fn set_parmrk(fd: RawFd) -> io::Result<libc::tcflag_t> {
+    let mut tio = tcgets2(fd)?;
+    let iflag = tio.c_iflag;
+    tio.c_iflag |= libc::PARMRK;
+    tio.c_iflag &= !(libc::IGNBRK | libc::BRKINT | libc::IGNPAR | libc::ISTRIP);
+    tcsets2(fd, &tio)?;
+    Ok(iflag)
+}
+
+/// Replace the input mode flags (`c_iflag`) wholesale.
+pub fn set_iflag(fd: RawFd, iflag: libc::tcflag_t) -> io::Result<()> {
+    let mut tio = tcgets2(fd)?;
+    tio.c_iflag = iflag;
+    tcsets2(fd, &tio)
+}
+
+/// `struct serial_icounter_struct` from `<linux/serial.h>`
+#[repr(C)]
+#[derive(Default)]
+struct SerialIcounter {
+    cts: libc::c_int,
+    dsr: libc::c_int,
+    rng: libc::c_int,
+    dcd: libc::c_int,
+    rx: libc::c_int,
+    tx: libc::c_int,
+    frame: libc::c_int,
+    overrun: libc::c_int,
+    parity: libc::c_int,
+    brk: libc::c_int,
+    buf_overrun: libc::c_int,
+    reserved: [libc::c_int; 9],
+}
+
+/// Read the driver's error counters with `TIOCGICOUNT`.
+///
+/// Returns `Ok(None)` for ttys that don't keep counters (PTYs, most USB
+/// CDC-ACM devices).
+pub fn get_icount(fd: RawFd) -> io::Result<Option<DriverCounters>> {
+    let mut ic = SerialIcounter::default();
+    if unsafe { libc::ioctl(fd, libc::TIOCGICOUNT, &mut ic) } < 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENOTTY) | Some(libc::EINVAL) => Ok(None),
+            _ => Err(err),
+        };
+    }
+    // The kernel counters are u32 that wrap; keep them as unsigned
+    Ok(Some(DriverCounters {
+        overrun: ic.overrun as u32,
+        frame: ic.frame as u32,
+        parity: ic.parity as u32,
+        brk: ic.brk as u32,
+        buf_overrun: ic.buf_overrun as u32,
+    }))
+}
+
+const SER_RS485_ENABLED: u32 = 1 << 0;
+const SER_RS485_RTS_ON_SEND: u32 = 1 << 1;
+
+/// `struct serial_rs485` from `<linux/serial.h>`
+#[repr(C)]
+#[derive(Default)]
+struct SerialRs485 {
+    flags: u32,
+    delay_rts_before_send: u32,
+    delay_rts_after_send: u32,
+    padding: [u32; 5],
+}
+
+/// Enable RS-485 mode with `TIOCSRS485`: RTS high while sending, low
+/// otherwise, with the given delays (ms) around each transmission.
+pub fn set_rs485(fd: RawFd, delay_before_ms: u32, delay_after_ms: u32) -> io::Result<()> {
+    let conf = SerialRs485 {
+        flags: SER_RS485_ENABLED | SER_RS485_RTS_ON_SEND,
+        delay_rts_before_send: delay_before_ms,
+        delay_rts_after_send: delay_after_ms,
+        ..SerialRs485::default()
+    };
+    if unsafe { libc::ioctl(fd, libc::TIOCSRS485, &conf) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
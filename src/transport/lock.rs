@@ -0,0 +1,246 @@
+use anyhow::{Result, bail};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Where minicom, picocom, screen and friends put their UUCP lock files
+pub const DEFAULT_LOCK_DIR: &str = "/var/lock";
+
+/// A process holding a device open
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortUser {
+    pub pid: u32,
+    pub comm: String,
+}
+
+impl std::fmt::Display for PortUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (pid {})", self.comm, self.pid)
+    }
+}
+
+/// UUCP-style `LCK..<tty>` lock file, removed again on drop.
+///
+/// The file holds our pid as ten right-aligned digits (HDB format). A lock
+/// left behind by a process that no longer exists is taken over.
+#[derive(Debug)]
+pub struct PortLock {
+    path: PathBuf,
+}
+
+impl PortLock {
+    /// Lock `dev` in `lock_dir`. Returns `Ok(None)` when the directory
+    /// can't be written (missing, or root-only): TIOCEXCL still protects us
+    /// from other openers, only the cooperative lock is skipped.
+    ///
+    /// The pid goes into a temp file that is then hard-linked into place,
+    /// so the lock file never exists without a pid in it.
+    pub fn acquire(dev: &str, lock_dir: &Path) -> Result<Option<Self>> {
+        let path = lock_dir.join(lock_name(dev));
+        let tmp = lock_dir.join(format!("LTMP.{}", std::process::id()));
+        if let Err(e) = fs::write(&tmp, format!("{:>10}\n", std::process::id())) {
+            crate::debug_eprintln!(
+                "[port] not locking {} in {}: {}",
+                dev,
+                lock_dir.display(),
+                e
+            );
+            return Ok(None);
+        }
+        let linked = link_lock(dev, &tmp, &path);
+        let _ = fs::remove_file(&tmp);
+        Ok(linked?.then_some(Self { path }))
+    }
+}
+
+/// Link `tmp` to `path`, taking over a stale lock once. `Ok(false)` when
+/// the directory won't take a link.
+fn link_lock(dev: &str, tmp: &Path, path: &Path) -> Result<bool> {
+    for _ in 0..2 {
+        match fs::hard_link(tmp, path) {
+            Ok(()) => return Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                match read_lock_pid(path) {
+                    Some(pid) if pid == std::process::id() => {
+                        bail!("{} is already open in this process", dev)
+                    }
+                    Some(pid) if process_alive(pid) => bail!(
+                        "locked by {} via {}",
+                        PortUser {
+                            pid,
+                            comm: comm_of(Path::new("/proc"), pid),
+                        },
+                        path.display()
+                    ),
+                    Some(_) => {
+                        eprintln!("[port] removing stale lock {}", path.display());
+                        fs::remove_file(path)?;
+                    }
+                    // Another opener may not have written its pid yet
+                    None => bail!(
+                        "{} is locked ({} holds no pid; remove it if stale)",
+                        dev,
+                        path.display()
+                    ),
+                }
+            }
+            Err(e) => {
+                crate::debug_eprintln!("[port] not locking {}: {}", dev, e);
+                return Ok(false);
+            }
+        }
+    }
+    bail!(
+        "{} is locked ({}), lost the race to take over a stale lock",
+        dev,
+        path.display()
+    )
+}
+
+impl Drop for PortLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// `LCK..ttyUSB0` for `/dev/ttyUSB0`, or for a by-id link pointing at it
+pub fn lock_name(dev: &str) -> String {
+    let resolved = Path::new(dev)
+        .canonicalize()
+        .unwrap_or_else(|_| PathBuf::from(dev));
+    let name = resolved
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| dev.replace('/', "_"));
+    format!("LCK..{}", name)
+}
+
+/// Pid in a lock file, in either the ASCII (HDB) or the old binary format
+fn read_lock_pid(path: &Path) -> Option<u32> {
+    let data = fs::read(path).ok()?;
+    if let Ok(pid) = String::from_utf8_lossy(&data).trim().parse() {
+        return Some(pid);
+    }
+    let raw: [u8; 4] = data.get(..4)?.try_into().ok()?;
+    Some(u32::from_ne_bytes(raw))
+}
+
+fn process_alive(pid: u32) -> bool {
+    // Signal 0 only checks; EPERM still means it exists
+    let rc = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    rc == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+fn comm_of(proc: &Path, pid: u32) -> String {
+    fs::read_to_string(proc.join(pid.to_string()).join("comm"))
+        .map(|s| s.trim().to_string())
+        .unwrap_or_else(|_| "?".to_string())
+}
+
+/// Processes with an fd open on `node`, from `<proc>/<pid>/fd`. Without root
+/// only our own user's processes are visible.
+pub fn find_users(proc: &Path, node: &Path) -> Vec<PortUser> {
+    let Ok(dir) = fs::read_dir(proc) else {
+        return Vec::new();
+    };
+    let mut users = Vec::new();
+    for entry in dir.flatten() {
+        let Ok(pid) = entry.file_name().to_string_lossy().parse::<u32>() else {
+            continue;
+        };
+        let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        let holds = fds
+            .flatten()
+            .any(|fd| fs::read_link(fd.path()).is_ok_and(|t| t == node));
+        if holds {
+            users.push(PortUser {
+                pid,
+                comm: comm_of(proc, pid),
+            });
+        }
+    }
+    users.sort_by_key(|u| u.pid);
+    users
+}
+
+/// Who else has `dev` open, for an error message ("" if nobody we can see)
+pub fn describe_holders(dev: &str) -> String {
+    let node = Path::new(dev)
+        .canonicalize()
+        .unwrap_or_else(|_| PathBuf::from(dev));
+    let me = std::process::id();
+    let users: Vec<String> = find_users(Path::new("/proc"), &node)
+        .into_iter()
+        .filter(|u| u.pid != me)
+        .map(|u| u.to_string())
+        .collect();
+    if users.is_empty() {
+        String::new()
+    } else {
+        format!("held by {}", users.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lock_dir(tag: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hammer-lock-{}-{}", tag, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn lock_is_exclusive_and_released() {
+        let dir = lock_dir("excl");
+        let lock = PortLock::acquire("/dev/ttyHAMMER0", &dir).unwrap().unwrap();
+        let file = dir.join("LCK..ttyHAMMER0");
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            format!("{:>10}\n", std::process::id())
+        );
+        let again = PortLock::acquire("/dev/ttyHAMMER0", &dir).unwrap_err();
+        assert!(again.to_string().contains("already open"), "{again}");
+        drop(lock);
+        assert!(!file.exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn lock_held_by_live_process_is_reported_and_stale_one_taken_over() {
+        let dir = lock_dir("stale");
+        let file = dir.join("LCK..ttyHAMMER1");
+        // pid 1 is always there
+        fs::write(&file, format!("{:>10}\n", 1)).unwrap();
+        let err = PortLock::acquire("/dev/ttyHAMMER1", &dir).unwrap_err();
+        assert!(err.to_string().contains("(pid 1)"), "{err}");
+
+        // Beyond pid_max, so never alive
+        fs::write(&file, format!("{:>10}\n", 999_999_999)).unwrap();
+        let lock = PortLock::acquire("/dev/ttyHAMMER1", &dir).unwrap();
+        assert!(lock.is_some());
+        drop(lock);
+
+        // Created but not yet written by its owner: still held
+        fs::write(&file, "").unwrap();
+        let err = PortLock::acquire("/dev/ttyHAMMER1", &dir).unwrap_err();
+        assert!(err.to_string().contains("holds no pid"), "{err}");
+        assert!(file.exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn missing_lock_dir_is_not_fatal() {
+        let dir = std::env::temp_dir().join("hammer-lock-does-not-exist");
+        assert!(
+            PortLock::acquire("/dev/ttyHAMMER2", &dir)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,472 @@
+//! In-memory UART link with a baud-accurate timing model, for tests.
+//!
+//! Bytes written on one end are serialized into start/data/parity/stop bits at
+//! the writer's baud and re-sampled at the reader's baud, so a clock mismatch
+//! turns into the same corruption a real receiver would see. Arrivals land in
+//! a finite RX FIFO that the "driver" drains every `service_interval`; bytes
+//! that do not fit are dropped and counted as overruns.
+//!
+//! Time on the wire is virtual: the line is treated as saturated, every byte
+//! starts right after the previous one. Nothing sleeps, so results depend only
+//! on the bytes written and the [`LinkModel`].
+
+use anyhow::Result;
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cli::PortConfig,
+    port::DEFAULT_CONFIG,
+    proto::command::{FlowControl, Parity},
+    stats::DriverCounters,
+    transport::{ModemLines, Transport},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct LinkModel {
+    /// RX hardware FIFO depth in bytes
+    pub fifo_depth: usize,
+    /// How often the receiving driver drains its FIFO
+    pub service_interval: Duration,
+    /// Clock error of end B relative to end A, in parts per million
+    pub skew_ppm: f64,
+    /// CTS inputs read low whatever the peer's RTS does (broken adapter)
+    pub dead_cts: bool,
+    /// Driver drops CRTSCTS on retune without saying so
+    pub no_crtscts: bool,
+}
+
+impl Default for LinkModel {
+    fn default() -> Self {
+        Self {
+            fifo_depth: 64,
+            service_interval: Duration::from_micros(100),
+            skew_ppm: 0.0,
+            dead_cts: false,
+            no_crtscts: false,
+        }
+    }
+}
+
+/// Receive-side error counters of one direction of the link
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LinkCounters {
+    pub overruns: u64,
+    pub frame: u64,
+    pub parity: u64,
+    pub brk: u64,
+}
+
+/// One direction of the link, as seen by its receiver
+#[derive(Default)]
+struct Wire {
+    /// Bytes handed to the reader (the tty buffer)
+    buf: VecDeque<u8>,
+    /// Virtual time (s) at which the line is free for the next character
+    line_free_at: f64,
+    fifo_fill: usize,
+    next_service: f64,
+    counters: LinkCounters,
+    /// Receiver marks breaks and bad characters (termios PARMRK)
+    parmrk: bool,
+}
+
+struct Link {
+    model: LinkModel,
+    cfg: [PortConfig; 2],
+    /// `wires[i]` carries bytes written by end `i`
+    wires: [Wire; 2],
+    /// (DTR, RTS) driven by each end; null-modem wired to the peer's
+    /// DSR+CD and CTS
+    outputs: [(bool, bool); 2],
+}
+
+impl Link {
+    fn baud(&self, side: usize) -> f64 {
+        let ppm = if side == 0 { 0.0 } else { self.model.skew_ppm };
+        self.cfg[side].baud as f64 * (1.0 + ppm * 1e-6)
+    }
+
+    fn transmit(&mut self, side: usize, data: &[u8]) {
+        let (tx_cfg, rx_cfg) = (self.cfg[side], self.cfg[1 - side]);
+        let (tx_bit, rx_bit) = (1.0 / self.baud(side), 1.0 / self.baud(1 - side));
+        let depth = self.model.fifo_depth;
+        let service = self.model.service_interval.as_secs_f64();
+        let flow = matches!(tx_cfg.flow, FlowControl::RtsCts)
+            && matches!(rx_cfg.flow, FlowControl::RtsCts);
+        let wire = &mut self.wires[side];
+
+        for &byte in data {
+            let bits = encode(byte, &tx_cfg);
+            let arrival = wire.line_free_at + bits.len() as f64 * tx_bit;
+            wire.line_free_at = arrival;
+
+            let rx = sample(&bits, tx_bit, rx_bit, &rx_cfg);
+            if rx.framing {
+                wire.counters.frame += 1;
+            }
+            if rx.parity {
+                wire.counters.parity += 1;
+            }
+
+            if arrival >= wire.next_service {
+                wire.fifo_fill = 0;
+                wire.next_service = ((arrival / service).floor() + 1.0) * service;
+            }
+            if wire.fifo_fill >= depth && !flow {
+                wire.counters.overruns += 1;
+                continue;
+            }
+            wire.fifo_fill += 1;
+
+            // With INPCK set the tty layer hands bad characters over as NUL,
+            // or as FF 00 <c> with PARMRK (where a real FF is doubled)
+            let checked = !matches!(rx_cfg.parity, Parity::None);
+            let bad = checked && (rx.framing || rx.parity);
+            match (bad, wire.parmrk) {
+                (true, true) => wire.buf.extend([0xFF, 0x00, rx.byte]),
+                (true, false) => wire.buf.push_back(0),
+                (false, true) if rx.byte == 0xFF => wire.buf.extend([0xFF, 0xFF]),
+                (false, _) => wire.buf.push_back(rx.byte),
+            }
+        }
+    }
+}
+
+/// Serialize one character into line levels: start, data LSB first, parity, stop.
+fn encode(byte: u8, cfg: &PortConfig) -> Vec<bool> {
+    let mut bits = Vec::with_capacity(cfg.bits_per_byte() as usize);
+    bits.push(false);
+    let mut ones = 0;
+    for i in 0..cfg.bits {
+        let bit = byte >> i & 1 == 1;
+        ones += bit as u32;
+        bits.push(bit);
+    }
+    match cfg.parity {
+        Parity::None => {}
+        Parity::Even => bits.push(!ones.is_multiple_of(2)),
+        Parity::Odd => bits.push(ones.is_multiple_of(2)),
+    }
+    bits.extend(std::iter::repeat_n(true, cfg.stop_bits as usize));
+    bits
+}
+
+struct Sampled {
+    byte: u8,
+    framing: bool,
+    parity: bool,
+}
+
+/// Sample a serialized character the way a receiver with bit time `rx_bit`
+/// does: sync on the start edge, then read each bit in its nominal middle.
+fn sample(bits: &[bool], tx_bit: f64, rx_bit: f64, cfg: &PortConfig) -> Sampled {
+    let level = |k: u32| {
+        let at = ((k as f64 + 0.5) * rx_bit / tx_bit) as usize;
+        bits.get(at).copied().unwrap_or(true) // idle line after the frame
+    };
+
+    let mut byte = 0u8;
+    let mut ones = 0;
+    for i in 0..cfg.bits as u32 {
+        if level(1 + i) {
+            byte |= 1 << i;
+            ones += 1;
+        }
+    }
+    let mut k = 1 + cfg.bits as u32;
+    let parity = match cfg.parity {
+        Parity::None => false,
+        Parity::Even | Parity::Odd => {
+            let bit = level(k) as u32;
+            k += 1;
+            let even = (ones + bit).is_multiple_of(2);
+            matches!(cfg.parity, Parity::Even) != even
+        }
+    };
+    Sampled {
+        byte,
+        framing: !level(k),
+        parity,
+    }
+}
+
+/// One end of an in-memory link
+pub struct LoopbackTransport {
+    link: Arc<(Mutex<Link>, Condvar)>,
+    side: usize,
+    timeout: Duration,
+}
+
+/// Create a connected pair of ends (A, B), both starting at the default config.
+pub fn pair(model: LinkModel) -> (LoopbackTransport, LoopbackTransport) {
+    let link = Arc::new((
+        Mutex::new(Link {
+            model,
+            cfg: [DEFAULT_CONFIG; 2],
+            wires: Default::default(),
+            outputs: [(true, true); 2],
+        }),
+        Condvar::new(),
+    ));
+    let end = |side| LoopbackTransport {
+        link: link.clone(),
+        side,
+        timeout: Duration::from_millis(100),
+    };
+    (end(0), end(1))
+}
+
+impl LoopbackTransport {
+    /// Error counters for the bytes this end received
+    pub fn counters(&self) -> LinkCounters {
+        self.link.0.lock().unwrap().wires[1 - self.side].counters
+    }
+
+    /// Virtual time this end's transmitter has spent on the wire
+    pub fn wire_time(&self) -> Duration {
+        Duration::from_secs_f64(self.link.0.lock().unwrap().wires[self.side].line_free_at)
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn retune(&mut self, cfg: &PortConfig) -> Result<()> {
+        let mut link = self.link.0.lock().unwrap();
+        let mut cfg = *cfg;
+        if link.model.no_crtscts && cfg.flow == FlowControl::RtsCts {
+            cfg.flow = FlowControl::None;
+        }
+        link.cfg[self.side] = cfg;
+        Ok(())
+    }
+
+    fn applied_config(&self) -> Result<Option<PortConfig>> {
+        Ok(Some(self.link.0.lock().unwrap().cfg[self.side]))
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.link.0.lock().unwrap().wires[1 - self.side].buf.clear();
+        Ok(())
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(Self {
+            link: self.link.clone(),
+            side: self.side,
+            timeout: self.timeout,
+        }))
+    }
+
+    fn set_break(&mut self) -> Result<()> {
+        let (lock, cond) = &*self.link;
+        let wire = &mut lock.lock().unwrap().wires[self.side];
+        wire.counters.brk += 1;
+        if wire.parmrk {
+            wire.buf.extend([0xFF, 0x00, 0x00]);
+        } else {
+            wire.buf.push_back(0);
+        }
+        cond.notify_all();
+        Ok(())
+    }
+
+    fn clear_break(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_parmrk(&mut self, on: bool) -> Result<()> {
+        self.link.0.lock().unwrap().wires[1 - self.side].parmrk = on;
+        Ok(())
+    }
+
+    fn write_lines(&mut self, dtr: bool, rts: bool) -> Result<()> {
+        self.link.0.lock().unwrap().outputs[self.side] = (dtr, rts);
+        Ok(())
+    }
+
+    fn read_lines(&mut self) -> Result<ModemLines> {
+        let link = self.link.0.lock().unwrap();
+        let (dtr, rts) = link.outputs[1 - self.side];
+        Ok(ModemLines {
+            cts: rts && !link.model.dead_cts,
+            dsr: dtr,
+            cd: dtr,
+            ri: false,
+        })
+    }
+
+    fn error_counters(&self) -> Result<Option<DriverCounters>> {
+        let c = self.counters();
+        // Narrowed like the kernel's 32-bit counters
+        Ok(Some(DriverCounters {
+            overrun: c.overruns as u32,
+            frame: c.frame as u32,
+            parity: c.parity as u32,
+            brk: c.brk as u32,
+            ..DriverCounters::default()
+        }))
+    }
+}
+
+impl Read for LoopbackTransport {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let (lock, cond) = &*self.link;
+        let deadline = Instant::now() + self.timeout;
+        let mut link = lock.lock().unwrap();
+        loop {
+            let buf = &mut link.wires[1 - self.side].buf;
+            if !buf.is_empty() {
+                let n = out.len().min(buf.len());
+                for (dst, src) in out.iter_mut().zip(buf.drain(..n)) {
+                    *dst = src;
+                }
+                return Ok(n);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "loopback read timed out",
+                ));
+            }
+            link = cond.wait_timeout(link, left).unwrap().0;
+        }
+    }
+}
+
+impl Write for LoopbackTransport {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let (lock, cond) = &*self.link;
+        lock.lock().unwrap().transmit(self.side, data);
+        cond.notify_all();
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg(bits: u8, parity: Parity) -> PortConfig {
+        PortConfig {
+            bits,
+            parity,
+            ..DEFAULT_CONFIG
+        }
+    }
+
+    #[test]
+    fn matched_clocks_roundtrip() {
+        let (mut a, mut b) = pair(LinkModel::default());
+        a.write_all(b"hello\r\n").unwrap();
+        let mut got = [0u8; 7];
+        b.read_exact(&mut got).unwrap();
+        assert_eq!(&got, b"hello\r\n");
+        let c = b.counters();
+        assert_eq!((c.overruns, c.frame, c.parity), (0, 0, 0));
+    }
+
+    #[test]
+    fn sampling_tolerates_small_skew_only() {
+        let c = cfg(8, Parity::None);
+        let bits = encode(0x55, &c);
+        // 2% slow receiver still reads the stop bit inside its bit cell
+        let ok = sample(&bits, 1.0, 1.02, &c);
+        assert_eq!((ok.byte, ok.framing), (0x55, false));
+        // 10% slow receiver drifts a full bit by the end of the frame
+        let bad = sample(&bits, 1.0, 1.10, &c);
+        assert!(bad.byte != 0x55 || bad.framing);
+    }
+
+    #[test]
+    fn parity_mismatch_is_counted() {
+        let (mut a, mut b) = pair(LinkModel::default());
+        a.retune(&cfg(8, Parity::Even)).unwrap();
+        b.retune(&cfg(8, Parity::Odd)).unwrap();
+        a.write_all(&[0x01]).unwrap();
+        let mut got = [0xFFu8; 1];
+        b.read_exact(&mut got).unwrap();
+        assert_eq!(got[0], 0);
+        assert_eq!(b.counters().parity, 1);
+    }
+
+    #[test]
+    fn five_bit_link_carries_nibble_frames() {
+        use crate::frame::{FrameFormat, NIBBLE_END};
+
+        let (mut a, mut b) = pair(LinkModel::default());
+        let five = cfg(5, Parity::None);
+        a.retune(&five).unwrap();
+        b.retune(&five).unwrap();
+
+        // Hex text loses its upper bits on a 5-bit line
+        a.write_all(b"@@\r\n").unwrap();
+        let mut got = [0u8; 4];
+        b.read_exact(&mut got).unwrap();
+        assert_ne!(&got, b"@@\r\n");
+
+        let format = FrameFormat::for_bits(5);
+        let sent = format.encode(9, &[0xA5; 16]);
+        a.write_all(&sent).unwrap();
+        let mut got = vec![0u8; sent.len()];
+        b.read_exact(&mut got).unwrap();
+        assert_eq!(got.last(), Some(&NIBBLE_END));
+        assert_eq!(format.decode(&got).unwrap().seq, 9);
+    }
+
+    #[test]
+    fn fifo_overrun_drops_bytes() {
+        // 115200 8N1 is ~87us per byte, so a 1ms service interval sees ~11 bytes
+        let model = LinkModel {
+            fifo_depth: 8,
+            service_interval: Duration::from_millis(1),
+            ..LinkModel::default()
+        };
+        let (mut a, b) = pair(model);
+        a.write_all(&[b'x'; 115]).unwrap();
+        let c = b.counters();
+        assert!(c.overruns > 0);
+
+        // Hardware flow control on both ends stops the overrun
+        let (mut a, mut b) = pair(model);
+        let rtscts = PortConfig {
+            flow: FlowControl::RtsCts,
+            ..DEFAULT_CONFIG
+        };
+        a.retune(&rtscts).unwrap();
+        b.retune(&rtscts).unwrap();
+        a.write_all(&[b'x'; 115]).unwrap();
+        assert_eq!(b.counters().overruns, 0);
+    }
+
+    #[test]
+    fn wire_time_follows_baud() {
+        let (mut a, _b) = pair(LinkModel::default());
+        a.write_all(&[0u8; 11_520]).unwrap();
+        // 11520 bytes * 10 bits at 115200 baud = 1s
+        assert!((a.wire_time().as_secs_f64() - 1.0).abs() < 1e-9);
+
+        // A second stop bit makes every character 11 bits long
+        let (mut a, _b) = pair(LinkModel::default());
+        a.retune(&PortConfig {
+            stop_bits: 2,
+            ..DEFAULT_CONFIG
+        })
+        .unwrap();
+        a.write_all(&[0u8; 11_520]).unwrap();
+        assert!((a.wire_time().as_secs_f64() - 1.1).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,273 @@
+use anyhow::{Result, bail};
+use std::{
+    io::{self, Read, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use crate::{
+    cli::{PortConfig, Rs485Opts},
+    stats::DriverCounters,
+};
+
+pub mod line_reader;
+#[cfg(target_os = "linux")]
+pub mod linux;
+#[cfg(unix)]
+pub mod lock;
+#[cfg(test)]
+pub mod loopback;
+pub mod rfc2217;
+pub mod serial;
+pub mod socket;
+pub mod tcp;
+
+pub use line_reader::LineReader;
+pub use rfc2217::Rfc2217Transport;
+pub use serial::SerialTransport;
+pub use socket::SocketTransport;
+pub use tcp::TcpTransport;
+
+/// A byte link the control protocol and the data plane run over.
+///
+/// The real serial port implements this, other backends (PTYs, sockets,
+/// in-memory pipes) plug in behind it so the test logic stays the same.
+pub trait Transport: Read + Write + Send {
+    /// Apply line settings (baud, parity, data bits, flow control)
+    fn retune(&mut self, cfg: &PortConfig) -> Result<()>;
+
+    /// Set the timeout for a single blocking read/write
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()>;
+
+    /// Discard received bytes that have not been read yet. Output already
+    /// written is left alone: on a PTY or a remote port it may still be on
+    /// its way to the peer.
+    fn clear(&mut self) -> Result<()>;
+
+    /// Independent handle on the same link (e.g. a reader next to a writer)
+    fn try_clone(&self) -> Result<Box<dyn Transport>>;
+
+    /// Line settings as the driver actually applied them, if the backend
+    /// can tell (drivers may round the baud rate or ignore CRTSCTS)
+    fn applied_config(&self) -> Result<Option<PortConfig>> {
+        Ok(None)
+    }
+
+    /// Snapshot of the driver's receive error counters, if the backend has them
+    fn error_counters(&self) -> Result<Option<DriverCounters>> {
+        Ok(None)
+    }
+
+    /// Drive the DTR and RTS outputs
+    fn write_lines(&mut self, _dtr: bool, _rts: bool) -> Result<()> {
+        bail!("modem control lines are not supported on this transport")
+    }
+
+    /// Sample the CTS/DSR/CD/RI inputs
+    fn read_lines(&mut self) -> Result<ModemLines> {
+        bail!("modem control lines are not supported on this transport")
+    }
+
+    /// Start holding the TX line in break (low)
+    fn set_break(&mut self) -> Result<()> {
+        bail!("break is not supported on this transport")
+    }
+
+    /// Release the TX line from break
+    fn clear_break(&mut self) -> Result<()> {
+        bail!("break is not supported on this transport")
+    }
+
+    /// Mark received breaks and bad characters in the byte stream (termios
+    /// `PARMRK`): break → `FF 00 00`, framing/parity error → `FF 00 <c>`,
+    /// literal `FF` → `FF FF`. Turning it off puts back the input flags it
+    /// changed.
+    fn set_parmrk(&mut self, _on: bool) -> Result<()> {
+        bail!("PARMRK is not supported on this transport")
+    }
+
+    /// Switch the port into RS-485 half-duplex mode
+    fn set_rs485(&mut self, _opts: &Rs485Opts) -> Result<()> {
+        bail!("RS-485 mode is not supported on this transport")
+    }
+}
+
+/// Modem status inputs as sampled on one end
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ModemLines {
+    pub cts: bool,
+    pub dsr: bool,
+    pub cd: bool,
+    pub ri: bool,
+}
+
+/// How to open a transport, besides its initial line settings
+#[derive(Debug, Clone)]
+pub struct OpenOpts {
+    /// Timeout for a single blocking read/write
+    pub timeout: Duration,
+    /// Shell command run on retune for links configured out of band (tcp://)
+    pub retune_hook: Option<String>,
+    /// Directory for a UUCP `LCK..` file on local devices, `None` = don't
+    pub lock_dir: Option<PathBuf>,
+}
+
+impl Default for OpenOpts {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(100),
+            retune_hook: None,
+            lock_dir: default_lock_dir(),
+        }
+    }
+}
+
+/// UUCP lock directory on unix; Windows COM ports are exclusive anyway
+pub fn default_lock_dir() -> Option<PathBuf> {
+    #[cfg(unix)]
+    return Some(PathBuf::from(lock::DEFAULT_LOCK_DIR));
+    #[cfg(not(unix))]
+    None
+}
+
+/// Whether `err` means the link itself is gone (USB adapter unplugged, TCP
+/// peer closed) rather than a timeout or a protocol problem
+pub fn is_disconnect(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<serialport::Error>() {
+            return match e.kind() {
+                serialport::ErrorKind::NoDevice => true,
+                serialport::ErrorKind::Io(kind) => io_kind_is_gone(kind),
+                _ => false,
+            };
+        }
+        cause.downcast_ref::<io::Error>().is_some_and(|e| {
+            // EIO is what a tty read/ioctl returns once its USB device is gone
+            io_kind_is_gone(e.kind()) || e.raw_os_error().is_some_and(is_gone_errno)
+        })
+    })
+}
+
+fn io_kind_is_gone(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotFound
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+#[cfg(unix)]
+fn is_gone_errno(code: i32) -> bool {
+    matches!(code, libc::EIO | libc::ENXIO | libc::ENODEV)
+}
+
+#[cfg(not(unix))]
+fn is_gone_errno(_code: i32) -> bool {
+    false
+}
+
+fn peer_closed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        "peer closed the connection",
+    )
+}
+
+/// Throw away whatever a stream socket has already received. `MSG_DONTWAIT`
+/// keeps this from blocking without touching `O_NONBLOCK`, which clones of
+/// the socket share, so a reader on another handle never sees a spurious
+/// WouldBlock. A closed connection is an error, not an empty buffer.
+#[cfg(unix)]
+pub fn drain_socket(sock: &impl std::os::fd::AsRawFd) -> io::Result<()> {
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = unsafe {
+            libc::recv(
+                sock.as_raw_fd(),
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                libc::MSG_DONTWAIT,
+            )
+        };
+        match n {
+            0 => return Err(peer_closed()),
+            n if n > 0 => continue,
+            _ => {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::WouldBlock => return Ok(()),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(err),
+                }
+            }
+        }
+    }
+}
+
+/// Without `MSG_DONTWAIT`, the socket is made non-blocking for the drain.
+#[cfg(not(unix))]
+pub fn drain_socket(sock: &std::net::TcpStream) -> io::Result<()> {
+    sock.set_nonblocking(true)?;
+    let mut reader = sock;
+    let mut buf = [0u8; 1024];
+    let drained = loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break Err(peer_closed()),
+            Ok(_) => continue,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    sock.set_nonblocking(false)?;
+    drained
+}
+
+/// Open a transport for `dev` with the given initial line settings.
+///
+/// `dev` is a local serial device path, `rfc2217://host:port` for a remote
+/// port behind a Telnet COM-port server, or `tcp://host:port` for a raw
+/// byte stream (e.g. a ser2net "raw" port).
+pub fn open(dev: &str, cfg: &PortConfig, opts: &OpenOpts) -> Result<Box<dyn Transport>> {
+    if let Some(addr) = dev.strip_prefix("rfc2217://") {
+        return Ok(Box::new(Rfc2217Transport::connect(
+            addr,
+            cfg,
+            opts.timeout,
+        )?));
+    }
+    if let Some(addr) = dev.strip_prefix("tcp://") {
+        let mut port = TcpTransport::connect(addr, opts.retune_hook.clone(), opts.timeout)?;
+        port.retune(cfg)?;
+        return Ok(Box::new(port));
+    }
+    Ok(Box::new(SerialTransport::open(dev, cfg, opts)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, anyhow};
+
+    #[test]
+    fn disconnects_are_told_from_timeouts() {
+        let hup = io::Error::new(io::ErrorKind::BrokenPipe, "hangup");
+        assert!(is_disconnect(&anyhow::Error::from(hup)));
+        #[cfg(unix)]
+        {
+            let eio: Result<()> = Err(io::Error::from_raw_os_error(libc::EIO).into());
+            assert!(is_disconnect(
+                &eio.context("running max-rate test").unwrap_err()
+            ));
+        }
+        let gone = serialport::Error::new(serialport::ErrorKind::NoDevice, "gone");
+        assert!(is_disconnect(&anyhow::Error::from(gone)));
+
+        let slow = io::Error::new(io::ErrorKind::TimedOut, "timed out");
+        assert!(!is_disconnect(&anyhow::Error::from(slow)));
+        assert!(!is_disconnect(&anyhow!("timed out after 10s")));
+    }
+}
@@ -0,0 +1,461 @@
+//! RFC 2217 (Telnet COM-PORT-OPTION) client, for UARTs behind ser2net or
+//! terminal servers. Line settings go to the remote port as SET-BAUDRATE,
+//! SET-DATASIZE, SET-PARITY, SET-STOPSIZE and SET-CONTROL subnegotiations.
+
+use anyhow::{Result, bail};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Read, Write},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cli::PortConfig,
+    proto::command::{FlowControl, Parity},
+    transport::Transport,
+};
+
+// Telnet
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const OPT_BINARY: u8 = 0;
+const OPT_SGA: u8 = 3;
+const OPT_COM_PORT: u8 = 44;
+
+// COM-PORT-OPTION commands (client -> server; the server answers with +100)
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const PURGE_DATA: u8 = 12;
+const SERVER_OFFSET: u8 = 100;
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Event {
+    /// WILL/WONT/DO/DONT for an option
+    Negotiate(u8, u8),
+    /// Subnegotiation payload between IAC SB and IAC SE
+    Sub(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Data,
+    Iac,
+    Opt(u8),
+    Sb,
+    SbIac,
+}
+
+/// Telnet stream decoder: splits user data from negotiation traffic.
+#[derive(Debug)]
+struct Decoder {
+    state: State,
+    sb: Vec<u8>,
+    data: VecDeque<u8>,
+    events: Vec<Event>,
+}
+
+impl Decoder {
+    fn new() -> Self {
+        Self {
+            state: State::Data,
+            sb: Vec::new(),
+            data: VecDeque::new(),
+            events: Vec::new(),
+        }
+    }
+
+    fn feed(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.state = match (self.state, b) {
+                (State::Data, IAC) => State::Iac,
+                (State::Data, b) => {
+                    self.data.push_back(b);
+                    State::Data
+                }
+                (State::Iac, IAC) => {
+                    self.data.push_back(IAC);
+                    State::Data
+                }
+                (State::Iac, WILL | WONT | DO | DONT) => State::Opt(b),
+                (State::Iac, SB) => {
+                    self.sb.clear();
+                    State::Sb
+                }
+                (State::Iac, _) => State::Data, // NOP, GA, ...
+                (State::Opt(verb), opt) => {
+                    self.events.push(Event::Negotiate(verb, opt));
+                    State::Data
+                }
+                (State::Sb, IAC) => State::SbIac,
+                (State::Sb, b) => {
+                    self.sb.push(b);
+                    State::Sb
+                }
+                (State::SbIac, IAC) => {
+                    self.sb.push(IAC);
+                    State::Sb
+                }
+                (State::SbIac, SE) => {
+                    self.events.push(Event::Sub(std::mem::take(&mut self.sb)));
+                    State::Data
+                }
+                (State::SbIac, _) => State::Data, // malformed, drop it
+            };
+        }
+    }
+}
+
+/// Double every IAC so data bytes are not taken for commands.
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 2);
+    for &b in data {
+        out.push(b);
+        if b == IAC {
+            out.push(IAC);
+        }
+    }
+    out
+}
+
+fn subnegotiation(cmd: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![IAC, SB, OPT_COM_PORT, cmd];
+    out.extend(escape(value));
+    out.extend([IAC, SE]);
+    out
+}
+
+/// State shared by all clones of one connection
+struct Session {
+    decoder: Decoder,
+    /// Server has agreed to (true) or refused (false) COM-PORT-OPTION
+    com_port: Option<bool>,
+    /// Latest server answer per COM-PORT command (keyed by the client code)
+    replies: HashMap<u8, Vec<u8>>,
+}
+
+pub struct Rfc2217Transport {
+    stream: TcpStream,
+    session: Arc<Mutex<Session>>,
+    addr: String,
+}
+
+impl Rfc2217Transport {
+    /// Connect to `addr` (host:port), negotiate COM-PORT-OPTION and apply `cfg`.
+    pub fn connect(addr: &str, cfg: &PortConfig, timeout: Duration) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        let mut port = Self {
+            stream,
+            session: Arc::new(Mutex::new(Session {
+                decoder: Decoder::new(),
+                com_port: None,
+                replies: HashMap::new(),
+            })),
+            addr: addr.to_string(),
+        };
+        port.set_timeout(timeout)?;
+
+        port.stream.write_all(&[
+            IAC,
+            WILL,
+            OPT_BINARY,
+            IAC,
+            DO,
+            OPT_BINARY,
+            IAC,
+            WILL,
+            OPT_COM_PORT,
+        ])?;
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+        loop {
+            match port.session.lock().unwrap().com_port {
+                Some(true) => break,
+                Some(false) => bail!("{}: server refused COM-PORT-OPTION", addr),
+                None => {}
+            }
+            if Instant::now() >= deadline {
+                bail!("{}: no answer to COM-PORT-OPTION", addr);
+            }
+            port.pump_until_timeout()?;
+        }
+
+        port.retune(cfg)?;
+        Ok(port)
+    }
+
+    /// Read once from the socket and process whatever arrived.
+    fn pump(&mut self) -> io::Result<()> {
+        let mut buf = [0u8; 1024];
+        let n = self.stream.read(&mut buf)?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "rfc2217 server closed the connection",
+            ));
+        }
+
+        let mut answer = Vec::new();
+        let mut session = self.session.lock().unwrap();
+        session.decoder.feed(&buf[..n]);
+        for event in std::mem::take(&mut session.decoder.events) {
+            match event {
+                Event::Negotiate(DO, OPT_COM_PORT) => session.com_port = Some(true),
+                Event::Negotiate(DONT, OPT_COM_PORT) => session.com_port = Some(false),
+                Event::Negotiate(DO, OPT_BINARY | OPT_SGA) => {}
+                Event::Negotiate(WILL, OPT_BINARY | OPT_SGA) => {}
+                Event::Negotiate(DO, opt) => answer.extend([IAC, WONT, opt]),
+                Event::Negotiate(WILL, opt) => answer.extend([IAC, DONT, opt]),
+                Event::Negotiate(..) => {}
+                Event::Sub(sb) => {
+                    if let [OPT_COM_PORT, cmd, value @ ..] = sb.as_slice()
+                        && *cmd >= SERVER_OFFSET
+                    {
+                        session.replies.insert(cmd - SERVER_OFFSET, value.to_vec());
+                    }
+                }
+            }
+        }
+        drop(session);
+        if !answer.is_empty() {
+            self.stream.write_all(&answer)?;
+        }
+        Ok(())
+    }
+
+    /// Like `pump`, but a read timeout is not an error.
+    fn pump_until_timeout(&mut self) -> io::Result<()> {
+        match self.pump() {
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(())
+            }
+            other => other,
+        }
+    }
+
+    /// Send COM-PORT commands and wait until the server has answered each one.
+    fn command(&mut self, cmds: &[(u8, Vec<u8>)]) -> Result<()> {
+        let mut out = Vec::new();
+        {
+            let mut session = self.session.lock().unwrap();
+            for (cmd, value) in cmds {
+                session.replies.remove(cmd);
+                out.extend(subnegotiation(*cmd, value));
+            }
+        }
+        self.stream.write_all(&out)?;
+
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+        loop {
+            let missing = {
+                let session = self.session.lock().unwrap();
+                cmds.iter()
+                    .map(|(cmd, _)| *cmd)
+                    .find(|cmd| !session.replies.contains_key(cmd))
+            };
+            let Some(cmd) = missing else {
+                return Ok(());
+            };
+            if Instant::now() >= deadline {
+                bail!("{}: no reply to COM-PORT command {}", self.addr, cmd);
+            }
+            self.pump_until_timeout()?;
+        }
+    }
+}
+
+impl Transport for Rfc2217Transport {
+    fn retune(&mut self, cfg: &PortConfig) -> Result<()> {
+        let parity = match cfg.parity {
+            Parity::None => 1,
+            Parity::Odd => 2,
+            Parity::Even => 3,
+        };
+        let control = match cfg.flow {
+            FlowControl::None => 1,
+            FlowControl::RtsCts => 3,
+            FlowControl::XonXoff => 2,
+        };
+        self.command(&[
+            (SET_BAUDRATE, cfg.baud.to_be_bytes().to_vec()),
+            (SET_DATASIZE, vec![cfg.bits]),
+            (SET_PARITY, vec![parity]),
+            (SET_STOPSIZE, vec![cfg.stop_bits]),
+            (SET_CONTROL, vec![control]),
+        ])
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        self.stream.set_write_timeout(Some(timeout))?;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.command(&[(PURGE_DATA, vec![1])])?; // receive buffer only
+        self.session.lock().unwrap().decoder.data.clear();
+        Ok(())
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(Self {
+            stream: self.stream.try_clone()?,
+            session: self.session.clone(),
+            addr: self.addr.clone(),
+        }))
+    }
+}
+
+impl Read for Rfc2217Transport {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        loop {
+            {
+                let mut session = self.session.lock().unwrap();
+                let data = &mut session.decoder.data;
+                if !data.is_empty() {
+                    let n = out.len().min(data.len());
+                    for (dst, src) in out.iter_mut().zip(data.drain(..n)) {
+                        *dst = src;
+                    }
+                    return Ok(n);
+                }
+            }
+            self.pump()?;
+        }
+    }
+}
+
+impl Write for Rfc2217Transport {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.stream.write_all(&escape(data))?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        port::DEFAULT_CONFIG,
+        transport::{self, OpenOpts},
+    };
+    use std::{net::TcpListener, thread};
+
+    type Settings = Arc<Mutex<HashMap<u8, Vec<u8>>>>;
+
+    /// Stand-in RFC 2217 server: accepts COM-PORT-OPTION, records and echoes
+    /// every setting, and loops user data back to the client.
+    fn spawn_server() -> (String, Settings) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let settings: Settings = Default::default();
+        let recorded = settings.clone();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut decoder = Decoder::new();
+            let mut buf = [0u8; 1024];
+            while let Ok(n) = stream.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                decoder.feed(&buf[..n]);
+                let mut out = Vec::new();
+                for event in std::mem::take(&mut decoder.events) {
+                    match event {
+                        Event::Negotiate(WILL, opt) => out.extend([IAC, DO, opt]),
+                        Event::Negotiate(DO, opt) => out.extend([IAC, WILL, opt]),
+                        Event::Negotiate(..) => {}
+                        Event::Sub(sb) => {
+                            if let [OPT_COM_PORT, cmd, value @ ..] = sb.as_slice() {
+                                recorded.lock().unwrap().insert(*cmd, value.to_vec());
+                                out.extend(subnegotiation(cmd + SERVER_OFFSET, value));
+                            }
+                        }
+                    }
+                }
+                let data: Vec<u8> = decoder.data.drain(..).collect();
+                out.extend(escape(&data));
+                stream.write_all(&out).unwrap();
+            }
+        });
+        (addr, settings)
+    }
+
+    #[test]
+    fn decoder_handles_split_sequences() {
+        let mut d = Decoder::new();
+        d.feed(&[b'a', IAC]);
+        d.feed(&[IAC, b'b', IAC, SB, OPT_COM_PORT, 101, 0, 0, IAC]);
+        d.feed(&[IAC, 0, IAC, SE, IAC, DO]);
+        d.feed(&[OPT_COM_PORT]);
+        assert_eq!(
+            d.data.iter().copied().collect::<Vec<_>>(),
+            [b'a', IAC, b'b']
+        );
+        assert_eq!(
+            d.events,
+            [
+                Event::Sub(vec![OPT_COM_PORT, 101, 0, 0, IAC, 0]),
+                Event::Negotiate(DO, OPT_COM_PORT),
+            ]
+        );
+    }
+
+    #[test]
+    fn retune_reaches_server() {
+        let (addr, settings) = spawn_server();
+        let dev = format!("rfc2217://{}", addr);
+        let mut port = transport::open(&dev, &DEFAULT_CONFIG, &OpenOpts::default()).unwrap();
+        assert_eq!(
+            settings.lock().unwrap()[&SET_BAUDRATE],
+            115_200u32.to_be_bytes()
+        );
+
+        port.retune(&PortConfig {
+            baud: 57_600,
+            parity: Parity::Even,
+            bits: 7,
+            flow: FlowControl::RtsCts,
+            stop_bits: 1,
+        })
+        .unwrap();
+        let settings = settings.lock().unwrap();
+        assert_eq!(settings[&SET_BAUDRATE], 57_600u32.to_be_bytes());
+        assert_eq!(settings[&SET_DATASIZE], [7]);
+        assert_eq!(settings[&SET_PARITY], [3]);
+        assert_eq!(settings[&SET_STOPSIZE], [1]);
+        assert_eq!(settings[&SET_CONTROL], [3]);
+    }
+
+    #[test]
+    fn data_roundtrip_escapes_iac() {
+        let (addr, _) = spawn_server();
+        let mut port =
+            Rfc2217Transport::connect(&addr, &DEFAULT_CONFIG, Duration::from_millis(500)).unwrap();
+        let sent = [b'@', IAC, IAC, 0, b'#', b'\r', b'\n'];
+        port.write_all(&sent).unwrap();
+        let mut got = [0u8; 7];
+        port.read_exact(&mut got).unwrap();
+        assert_eq!(got, sent);
+    }
+}
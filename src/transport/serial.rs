@@ -0,0 +1,288 @@
+use anyhow::{Result, bail};
+use serialport::{ClearBuffer, DataBits, SerialPort};
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+use std::{
+    io::{self, Read, Write},
+    sync::Arc,
+    time::Duration,
+};
+
+#[cfg(target_os = "linux")]
+use crate::stats::DriverCounters;
+#[cfg(target_os = "linux")]
+use crate::transport::linux;
+#[cfg(unix)]
+use crate::transport::lock::{self, PortLock};
+use crate::{
+    cli::{PortConfig, Rs485Opts},
+    proto::command::{FlowControl, Parity},
+    transport::{ModemLines, OpenOpts, Transport},
+};
+
+#[cfg(unix)]
+type NativePort = serialport::TTYPort;
+#[cfg(windows)]
+type NativePort = serialport::COMPort;
+
+/// Transport backed by a local serial device (via the `serialport` crate).
+///
+/// Holds the platform port type rather than `Box<dyn SerialPort>` so the
+/// Linux path can reach the fd for ioctls `serialport` does not expose.
+pub struct SerialTransport {
+    port: NativePort,
+    /// Shared with clones, so the lock file goes when the last handle does
+    #[cfg(unix)]
+    _lock: Option<Arc<PortLock>>,
+    /// Input flags from before PARMRK went on, put back when it goes off
+    #[cfg(target_os = "linux")]
+    saved_iflag: Option<libc::tcflag_t>,
+}
+
+impl SerialTransport {
+    pub fn new(port: NativePort) -> Self {
+        Self {
+            port,
+            #[cfg(unix)]
+            _lock: None,
+            #[cfg(target_os = "linux")]
+            saved_iflag: None,
+        }
+    }
+
+    /// Open `dev` for exclusive use. `serialport` sets TIOCEXCL, so a second
+    /// opener gets EBUSY; tools that honour UUCP locks (minicom, picocom) are
+    /// kept out by the `LCK..` file in `opts.lock_dir`. On failure the
+    /// processes holding the device are named.
+    pub fn open(dev: &str, cfg: &PortConfig, opts: &OpenOpts) -> Result<Self> {
+        #[cfg(unix)]
+        let lock = match &opts.lock_dir {
+            Some(dir) => PortLock::acquire(dev, dir)?.map(Arc::new),
+            None => None,
+        };
+        let builder = serialport::new(dev, cfg.baud)
+            .timeout(opts.timeout)
+            .data_bits(data_bits(cfg.bits)?)
+            .parity(parity(cfg.parity))
+            .stop_bits(stop_bits(cfg.stop_bits)?)
+            .flow_control(flow_control(cfg.flow));
+
+        let native = builder.open_native().map_err(|e| {
+            #[cfg(unix)]
+            {
+                let holders = lock::describe_holders(dev);
+                if !holders.is_empty() {
+                    return anyhow::anyhow!("{} ({})", e, holders);
+                }
+            }
+            anyhow::Error::from(e)
+        })?;
+        let port = Self {
+            port: native,
+            #[cfg(unix)]
+            _lock: lock,
+            #[cfg(target_os = "linux")]
+            saved_iflag: None,
+        };
+        // Non-standard rates (250000, 1843200, ...) go through termios2
+        #[cfg(target_os = "linux")]
+        linux::set_baud(port.port.as_raw_fd(), cfg.baud)?;
+        Ok(port)
+    }
+
+    /// Open a connected pseudo-terminal pair (master end, slave end).
+    #[cfg(unix)]
+    pub fn pty_pair() -> Result<(Self, Self)> {
+        let (master, slave) = serialport::TTYPort::pair()?;
+        Ok((Self::new(master), Self::new(slave)))
+    }
+}
+
+fn data_bits(bits: u8) -> Result<DataBits> {
+    Ok(match bits {
+        5 => DataBits::Five,
+        6 => DataBits::Six,
+        7 => DataBits::Seven,
+        8 => DataBits::Eight,
+        other => bail!("unsupported data bits: {}", other),
+    })
+}
+
+fn stop_bits(stop_bits: u8) -> Result<serialport::StopBits> {
+    Ok(match stop_bits {
+        1 => serialport::StopBits::One,
+        2 => serialport::StopBits::Two,
+        other => bail!("unsupported stop bits: {}", other),
+    })
+}
+
+fn parity(parity: Parity) -> serialport::Parity {
+    match parity {
+        Parity::None => serialport::Parity::None,
+        Parity::Even => serialport::Parity::Even,
+        Parity::Odd => serialport::Parity::Odd,
+    }
+}
+
+fn flow_control(flow: FlowControl) -> serialport::FlowControl {
+    match flow {
+        FlowControl::None => serialport::FlowControl::None,
+        FlowControl::RtsCts => serialport::FlowControl::Hardware,
+        FlowControl::XonXoff => serialport::FlowControl::Software,
+    }
+}
+
+impl Transport for SerialTransport {
+    fn retune(&mut self, cfg: &PortConfig) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        linux::set_baud(self.port.as_raw_fd(), cfg.baud)?;
+        #[cfg(not(target_os = "linux"))]
+        self.port.set_baud_rate(cfg.baud)?;
+        self.port.set_data_bits(data_bits(cfg.bits)?)?;
+        self.port.set_parity(parity(cfg.parity))?;
+        self.port.set_stop_bits(stop_bits(cfg.stop_bits)?)?;
+        self.port.set_flow_control(flow_control(cfg.flow))?;
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.port.set_timeout(timeout)?;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        // Input only: on a PTY, flushing our output throws away what the
+        // other end has not read yet (e.g. the CONFIG SET ACK just written),
+        // and on a UART write_line has already drained it
+        self.port.clear(ClearBuffer::Input)?;
+        Ok(())
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(Self {
+            port: self.port.try_clone_native()?,
+            #[cfg(unix)]
+            _lock: self._lock.clone(),
+            #[cfg(target_os = "linux")]
+            saved_iflag: None,
+        }))
+    }
+
+    fn write_lines(&mut self, dtr: bool, rts: bool) -> Result<()> {
+        self.port.write_data_terminal_ready(dtr)?;
+        self.port.write_request_to_send(rts)?;
+        Ok(())
+    }
+
+    fn read_lines(&mut self) -> Result<ModemLines> {
+        Ok(ModemLines {
+            cts: self.port.read_clear_to_send()?,
+            dsr: self.port.read_data_set_ready()?,
+            cd: self.port.read_carrier_detect()?,
+            ri: self.port.read_ring_indicator()?,
+        })
+    }
+
+    fn set_break(&mut self) -> Result<()> {
+        self.port.set_break()?;
+        Ok(())
+    }
+
+    fn clear_break(&mut self) -> Result<()> {
+        self.port.clear_break()?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_parmrk(&mut self, on: bool) -> Result<()> {
+        let fd = self.port.as_raw_fd();
+        if on {
+            let iflag = linux::set_parmrk(fd)?;
+            self.saved_iflag.get_or_insert(iflag);
+        } else if let Some(iflag) = self.saved_iflag.take() {
+            linux::set_iflag(fd, iflag)?;
+        }
+        Ok(())
+    }
+
+    fn applied_config(&self) -> Result<Option<PortConfig>> {
+        // termios2 on Linux, since serialport may only know the standard rates
+        #[cfg(target_os = "linux")]
+        let baud = linux::get_baud(self.port.as_raw_fd())?;
+        #[cfg(not(target_os = "linux"))]
+        let baud = self.port.baud_rate()?;
+        Ok(Some(PortConfig {
+            baud,
+            parity: match self.port.parity()? {
+                serialport::Parity::None => Parity::None,
+                serialport::Parity::Even => Parity::Even,
+                serialport::Parity::Odd => Parity::Odd,
+            },
+            bits: match self.port.data_bits()? {
+                DataBits::Five => 5,
+                DataBits::Six => 6,
+                DataBits::Seven => 7,
+                DataBits::Eight => 8,
+            },
+            flow: match self.port.flow_control()? {
+                serialport::FlowControl::None => FlowControl::None,
+                serialport::FlowControl::Hardware => FlowControl::RtsCts,
+                serialport::FlowControl::Software => FlowControl::XonXoff,
+            },
+            stop_bits: match self.port.stop_bits()? {
+                serialport::StopBits::One => 1,
+                serialport::StopBits::Two => 2,
+            },
+        }))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn error_counters(&self) -> Result<Option<DriverCounters>> {
+        Ok(linux::get_icount(self.port.as_raw_fd())?)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_rs485(&mut self, opts: &Rs485Opts) -> Result<()> {
+        linux::set_rs485(
+            self.port.as_raw_fd(),
+            opts.rs485_delay_before_ms,
+            opts.rs485_delay_after_ms,
+        )?;
+        Ok(())
+    }
+}
+
+impl Read for SerialTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl Write for SerialTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::port::DEFAULT_CONFIG;
+
+    #[test]
+    fn pty_accepts_non_standard_rates() {
+        let (mut master, _slave) = SerialTransport::pty_pair().unwrap();
+        for baud in [250_000, 1_843_200, 2_500_000] {
+            let cfg = PortConfig {
+                baud,
+                ..DEFAULT_CONFIG
+            };
+            master.retune(&cfg).unwrap();
+            assert_eq!(master.applied_config().unwrap().unwrap().baud, baud);
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! Stream socket used as an out-of-band control channel (`--control-dev
+//! tcp://host:port` or `unix:///path`). The auto side listens, the master
+//! connects. There are no line settings, so retune does nothing.
+
+use anyhow::{Result, bail};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+use crate::{
+    cli::PortConfig,
+    transport::{Transport, drain_socket},
+};
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+pub struct SocketTransport {
+    stream: Stream,
+    /// Listening side: a closed connection is replaced by the next one
+    listener: Option<Listener>,
+    timeout: Duration,
+}
+
+impl Stream {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Stream::Tcp(s) => Stream::Tcp(s.try_clone()?),
+            #[cfg(unix)]
+            Stream::Unix(s) => Stream::Unix(s.try_clone()?),
+        })
+    }
+
+    fn set_timeout(&self, timeout: Duration) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => {
+                s.set_read_timeout(Some(timeout))?;
+                s.set_write_timeout(Some(timeout))
+            }
+            #[cfg(unix)]
+            Stream::Unix(s) => {
+                s.set_read_timeout(Some(timeout))?;
+                s.set_write_timeout(Some(timeout))
+            }
+        }
+    }
+
+    fn drain(&self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s) => drain_socket(s),
+            #[cfg(unix)]
+            Stream::Unix(s) => drain_socket(s),
+        }
+    }
+}
+
+impl Listener {
+    fn accept(&self) -> io::Result<Stream> {
+        Ok(match self {
+            Listener::Tcp(l) => {
+                let (s, peer) = l.accept()?;
+                s.set_nodelay(true)?;
+                eprintln!("[port] control connection from {}", peer);
+                Stream::Tcp(s)
+            }
+            #[cfg(unix)]
+            Listener::Unix(l) => {
+                let (s, _) = l.accept()?;
+                eprintln!("[port] control connection accepted");
+                Stream::Unix(s)
+            }
+        })
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Listener::Tcp(l) => Listener::Tcp(l.try_clone()?),
+            #[cfg(unix)]
+            Listener::Unix(l) => Listener::Unix(l.try_clone()?),
+        })
+    }
+}
+
+impl SocketTransport {
+    /// Whether `dev` names a socket rather than a device
+    pub fn is_socket(dev: &str) -> bool {
+        dev.starts_with("tcp://") || dev.starts_with("unix://")
+    }
+
+    pub fn connect(dev: &str, timeout: Duration) -> Result<Self> {
+        let stream = if let Some(addr) = dev.strip_prefix("tcp://") {
+            let s = TcpStream::connect(addr)?;
+            s.set_nodelay(true)?;
+            Stream::Tcp(s)
+        } else if let Some(path) = dev.strip_prefix("unix://") {
+            #[cfg(unix)]
+            {
+                Stream::Unix(UnixStream::connect(path)?)
+            }
+            #[cfg(not(unix))]
+            bail!("unix sockets are not available here: {}", path)
+        } else {
+            bail!("not a socket address: {}", dev)
+        };
+        let port = Self {
+            stream,
+            listener: None,
+            timeout,
+        };
+        port.stream.set_timeout(timeout)?;
+        Ok(port)
+    }
+
+    /// Bind `dev` and wait for the first connection
+    pub fn listen(dev: &str, timeout: Duration) -> Result<Self> {
+        let listener = if let Some(addr) = dev.strip_prefix("tcp://") {
+            Listener::Tcp(TcpListener::bind(addr)?)
+        } else if let Some(path) = dev.strip_prefix("unix://") {
+            #[cfg(unix)]
+            {
+                // A socket file left by an earlier run would make bind fail
+                let _ = std::fs::remove_file(path);
+                Listener::Unix(UnixListener::bind(path)?)
+            }
+            #[cfg(not(unix))]
+            bail!("unix sockets are not available here: {}", path)
+        } else {
+            bail!("not a socket address: {}", dev)
+        };
+        eprintln!("[port] control channel listening on {}", dev);
+        let stream = listener.accept()?;
+        stream.set_timeout(timeout)?;
+        Ok(Self {
+            stream,
+            listener: Some(listener),
+            timeout,
+        })
+    }
+}
+
+impl Transport for SocketTransport {
+    fn retune(&mut self, _cfg: &PortConfig) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.timeout = timeout;
+        self.stream.set_timeout(timeout)?;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.stream.drain()?;
+        Ok(())
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(Self {
+            stream: self.stream.try_clone()?,
+            listener: self
+                .listener
+                .as_ref()
+                .map(Listener::try_clone)
+                .transpose()?,
+            timeout: self.timeout,
+        }))
+    }
+}
+
+impl Read for SocketTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match &mut self.stream {
+            Stream::Tcp(s) => s.read(buf)?,
+            #[cfg(unix)]
+            Stream::Unix(s) => s.read(buf)?,
+        };
+        if n > 0 || buf.is_empty() {
+            return Ok(n);
+        }
+        match &self.listener {
+            // The master went away: wait for the next one
+            Some(listener) => {
+                eprintln!("[port] control connection closed, waiting for the next one");
+                self.stream = listener.accept()?;
+                self.stream.set_timeout(self.timeout)?;
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "control connection replaced",
+                ))
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "control peer closed the connection",
+            )),
+        }
+    }
+}
+
+impl Write for SocketTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.stream {
+            Stream::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.stream {
+            Stream::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.flush(),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn unix_control_socket_reconnects() {
+        let path = std::env::temp_dir().join(format!("hammer-ctl-{}.sock", std::process::id()));
+        let dev = format!("unix://{}", path.display());
+        let listen_dev = dev.clone();
+        let auto = thread::spawn(move || {
+            let mut port =
+                SocketTransport::listen(&listen_dev, Duration::from_millis(200)).unwrap();
+            let mut got = Vec::new();
+            let mut buf = [0u8; 16];
+            while got.len() < 4 {
+                match port.read(&mut buf) {
+                    Ok(n) => got.extend_from_slice(&buf[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => panic!("{e}"),
+                }
+            }
+            got
+        });
+
+        let connect = || loop {
+            if let Ok(p) = SocketTransport::connect(&dev, Duration::from_millis(200)) {
+                break p;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let mut first = connect();
+        first.write_all(b"ab").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        drop(first);
+        let mut second = connect();
+        second.write_all(b"cd").unwrap();
+
+        assert_eq!(auto.join().unwrap(), b"abcd");
+        let _ = std::fs::remove_file(path);
+    }
+}
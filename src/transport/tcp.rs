@@ -0,0 +1,190 @@
+//! Raw TCP byte stream, e.g. a ser2net "raw" port. The remote UART is
+//! configured out of band, so retune only runs the user's hook (if any).
+
+use anyhow::{Result, bail};
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    process::Command,
+    time::Duration,
+};
+
+use crate::{
+    cli::PortConfig,
+    proto::parser::{flow_to_str, parity_to_str},
+    transport::{Transport, drain_socket},
+};
+
+pub struct TcpTransport {
+    stream: TcpStream,
+    addr: String,
+    retune_hook: Option<String>,
+}
+
+impl TcpTransport {
+    pub fn connect(addr: &str, retune_hook: Option<String>, timeout: Duration) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        let mut port = Self {
+            stream,
+            addr: addr.to_string(),
+            retune_hook,
+        };
+        port.set_timeout(timeout)?;
+        Ok(port)
+    }
+}
+
+/// Run `hook` through the shell with the requested settings in the environment.
+fn run_retune_hook(hook: &str, addr: &str, cfg: &PortConfig) -> Result<()> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .env("HAMMER_DEV", format!("tcp://{}", addr))
+        .env("HAMMER_BAUD", cfg.baud.to_string())
+        .env("HAMMER_PARITY", parity_to_str(cfg.parity))
+        .env("HAMMER_BITS", cfg.bits.to_string())
+        .env("HAMMER_STOP_BITS", cfg.stop_bits.to_string())
+        .env("HAMMER_FLOW", flow_to_str(cfg.flow))
+        .status()?;
+    if !status.success() {
+        bail!("retune hook `{}` failed: {}", hook, status);
+    }
+    Ok(())
+}
+
+impl Transport for TcpTransport {
+    fn retune(&mut self, cfg: &PortConfig) -> Result<()> {
+        match &self.retune_hook {
+            Some(hook) => run_retune_hook(hook, &self.addr, cfg),
+            None => Ok(()), // remote side is configured separately
+        }
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        self.stream.set_write_timeout(Some(timeout))?;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        // Drop whatever already arrived; sent data cannot be recalled
+        drain_socket(&self.stream)?;
+        Ok(())
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(Self {
+            stream: self.stream.try_clone()?,
+            addr: self.addr.clone(),
+            retune_hook: self.retune_hook.clone(),
+        }))
+    }
+}
+
+impl Read for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.stream.read(buf)? {
+            // A closed socket would otherwise look like "no data yet" forever
+            0 if !buf.is_empty() => Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "tcp peer closed the connection",
+            )),
+            n => Ok(n),
+        }
+    }
+}
+
+impl Write for TcpTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::port::DEFAULT_CONFIG;
+    use std::{net::TcpListener, thread};
+
+    fn echo_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            while let Ok(n) = stream.read(&mut buf) {
+                if n == 0 || stream.write_all(&buf[..n]).is_err() {
+                    break;
+                }
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn bytes_pass_through_unchanged() {
+        let addr = echo_server();
+        let mut port = TcpTransport::connect(&addr, None, Duration::from_millis(500)).unwrap();
+        let sent: Vec<u8> = (0..=255).collect();
+        port.write_all(&sent).unwrap();
+        let mut got = vec![0u8; sent.len()];
+        port.read_exact(&mut got).unwrap();
+        assert_eq!(got, sent);
+    }
+
+    #[test]
+    fn clear_drops_pending_input_and_reports_a_closed_peer() {
+        let addr = echo_server();
+        let mut port = TcpTransport::connect(&addr, None, Duration::from_millis(500)).unwrap();
+        port.write_all(b"stale").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        port.clear().unwrap();
+        port.write_all(b"x").unwrap();
+        let mut got = [0u8; 1];
+        port.read_exact(&mut got).unwrap();
+        assert_eq!(&got, b"x");
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || drop(listener.accept().unwrap()));
+        let mut port = TcpTransport::connect(&addr, None, Duration::from_millis(500)).unwrap();
+        server.join().unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let err = port.clear().unwrap_err();
+        assert!(crate::transport::is_disconnect(&err), "{err:#}");
+    }
+
+    #[test]
+    fn retune_runs_hook_with_settings() {
+        let addr = echo_server();
+        let out = std::env::temp_dir().join(format!("hammer-hook-{}", std::process::id()));
+        let hook = format!(
+            "echo \"$HAMMER_BAUD $HAMMER_BITS $HAMMER_PARITY $HAMMER_FLOW\" > {}",
+            out.display()
+        );
+        let mut port =
+            TcpTransport::connect(&addr, Some(hook), Duration::from_millis(500)).unwrap();
+        port.retune(&PortConfig {
+            baud: 9_600,
+            ..DEFAULT_CONFIG
+        })
+        .unwrap();
+        let written = std::fs::read_to_string(&out).unwrap();
+        let _ = std::fs::remove_file(&out);
+        assert_eq!(written.trim(), "9600 8 none none");
+    }
+
+    #[test]
+    fn failing_hook_fails_retune() {
+        let addr = echo_server();
+        let mut port =
+            TcpTransport::connect(&addr, Some("exit 3".into()), Duration::from_millis(500))
+                .unwrap();
+        assert!(port.retune(&DEFAULT_CONFIG).is_err());
+    }
+}
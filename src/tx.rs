@@ -3,6 +3,7 @@ use std::io::Write;
 
 use crate::cli::{Pacing, TxOpts};
 use crate::frame::build_frame;
+use crate::pattern::Pattern;
 use crate::port::open_port;
 
 pub fn run(opts: TxOpts) -> Result<()> {
@@ -21,7 +22,7 @@ pub fn run(opts: TxOpts) -> Result<()> {
 
     loop {
         out.clear();
-        let line = build_frame(seq, opts.len);
+        let line = build_frame(seq, &Pattern::default().fill(seq, opts.len));
         if opts.debug {
             eprintln!("[tx] {}", line);
         }